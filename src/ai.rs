@@ -0,0 +1,286 @@
+//! A bot that picks moves with expectimax: our moves are max nodes,
+//! tile spawns are chance nodes weighted by `TileDistribution`.
+
+use bevy::prelude::*;
+use bevy_easings::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitboard::{Bitboard, SearchBoard},
+    input::MoveQueue,
+    persist,
+    rules::{BoardShift, GameState, TileDistribution},
+    Board, Points, Position, RunState,
+};
+
+const AI_CONFIG_FILE: &str = "ai.ron";
+/// What a board with no moves left is worth, well below anything the
+/// heuristic gives a live board.
+const LOST_SCORE: f32 = -1.0e6;
+/// Roughly how many boards one search may score. The search runs
+/// inside a frame, so past this it looks fewer moves ahead instead.
+const SEARCH_BUDGET: f32 = 200_000.0;
+
+/// How much each property of a board counts towards its score. All of
+/// them are measured on tile exponents, so a 2048 counts as 11.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeuristicWeights {
+    /// Rows and columns that only rise or only fall.
+    pub monotonicity: f32,
+    /// Neighbours close in value, ready to merge.
+    pub smoothness: f32,
+    pub empty_cells: f32,
+    /// The largest tile sitting in a corner.
+    pub corner_max: f32,
+}
+
+impl Default for HeuristicWeights {
+    fn default() -> Self {
+        HeuristicWeights {
+            monotonicity: 1.0,
+            smoothness: 0.1,
+            empty_cells: 2.7,
+            corner_max: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AiConfig {
+    /// How many of our own moves to look ahead, including the one
+    /// being picked. Each level multiplies the work by roughly four
+    /// times twice the number of empty cells, so large, open boards
+    /// get fewer levels; see `SEARCH_BUDGET`.
+    pub depth: u8,
+    pub weights: HeuristicWeights,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        AiConfig {
+            depth: 3,
+            weights: HeuristicWeights::default(),
+        }
+    }
+}
+
+impl AiConfig {
+    /// The expected score of every shift that changes the board.
+    /// 4x4 boards are searched packed into a `Bitboard`, anything else
+    /// on `GameState` itself.
+    pub fn evaluate_moves(
+        &self,
+        state: &GameState,
+        distribution: &TileDistribution,
+    ) -> Vec<(BoardShift, f32)> {
+        match Bitboard::from_state(state) {
+            Some(board) => self.evaluate_on(&board, distribution),
+            None => self.evaluate_on(state, distribution),
+        }
+    }
+
+    pub fn best_move(
+        &self,
+        state: &GameState,
+        distribution: &TileDistribution,
+    ) -> Option<BoardShift> {
+        self.evaluate_moves(state, distribution)
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(board_shift, _)| board_shift)
+    }
+
+    /// The deepest search that stays within `SEARCH_BUDGET` for a
+    /// board with `empty` free cells, and no deeper than `depth`.
+    fn depth_for(&self, empty: usize) -> u8 {
+        let branching = (8 * empty.max(1)) as f32;
+        let mut depth = 1;
+        let mut boards = 4.0;
+        while depth < self.depth && boards * branching <= SEARCH_BUDGET {
+            boards *= branching;
+            depth += 1;
+        }
+        depth
+    }
+
+    fn evaluate_on<B: SearchBoard>(
+        &self,
+        board: &B,
+        distribution: &TileDistribution,
+    ) -> Vec<(BoardShift, f32)> {
+        let depth = self.depth_for(board.empty_cells().len()) - 1;
+        BoardShift::ALL
+            .iter()
+            .filter_map(|board_shift| {
+                board.shifted(*board_shift).map(|next| {
+                    (*board_shift, self.chance_node(&next, depth, distribution))
+                })
+            })
+            .collect()
+    }
+
+    fn max_node<B: SearchBoard>(
+        &self,
+        board: &B,
+        depth: u8,
+        distribution: &TileDistribution,
+    ) -> f32 {
+        BoardShift::ALL
+            .iter()
+            .filter_map(|board_shift| {
+                board
+                    .shifted(*board_shift)
+                    .map(|next| self.chance_node(&next, depth, distribution))
+            })
+            .reduce(f32::max)
+            .unwrap_or(LOST_SCORE)
+    }
+
+    /// Averages over every cell a tile could land in and every value
+    /// it could have, then lets us move again.
+    fn chance_node<B: SearchBoard>(
+        &self,
+        board: &B,
+        depth: u8,
+        distribution: &TileDistribution,
+    ) -> f32 {
+        if depth == 0 {
+            return self.heuristic(board);
+        }
+        let empty = board.empty_cells();
+        if empty.is_empty() {
+            return self.heuristic(board);
+        }
+
+        let mut total = 0.0;
+        for (x, y) in empty.iter() {
+            for (value, probability) in distribution.probabilities() {
+                let mut next = board.clone();
+                next.set_rank(*x, *y, value.trailing_zeros() as u8);
+                total +=
+                    probability * self.max_node(&next, depth - 1, distribution);
+            }
+        }
+        total / empty.len() as f32
+    }
+
+    fn heuristic<B: SearchBoard>(&self, board: &B) -> f32 {
+        let size = board.size();
+        let rank = |x: u8, y: u8| f32::from(board.rank(x, y));
+        let lines: Vec<Vec<f32>> = (0..size)
+            .map(|y| (0..size).map(|x| rank(x, y)).collect())
+            .chain((0..size).map(|x| (0..size).map(|y| rank(x, y)).collect()))
+            .collect();
+
+        // only the steps against a line's main direction count
+        let monotonicity: f32 = lines
+            .iter()
+            .map(|line| {
+                let (rising, falling) = line.windows(2).fold(
+                    (0.0, 0.0),
+                    |(rising, falling), pair| {
+                        let step = pair[1] - pair[0];
+                        if step > 0.0 {
+                            (rising + step, falling)
+                        } else {
+                            (rising, falling - step)
+                        }
+                    },
+                );
+                -f32::min(rising, falling)
+            })
+            .sum();
+
+        // empty cells don't stand between two tiles
+        let smoothness: f32 = lines
+            .iter()
+            .map(|line| {
+                let tiles: Vec<f32> =
+                    line.iter().copied().filter(|rank| *rank > 0.0).collect();
+                -tiles
+                    .windows(2)
+                    .map(|pair| (pair[1] - pair[0]).abs())
+                    .sum::<f32>()
+            })
+            .sum();
+
+        let empty_cells = board.empty_cells().len() as f32;
+
+        let max = lines[..usize::from(size)]
+            .iter()
+            .flatten()
+            .copied()
+            .fold(0.0, f32::max);
+        let last = size - 1;
+        let corner_max = [(0, 0), (0, last), (last, 0), (last, last)]
+            .iter()
+            .any(|(x, y)| rank(*x, *y) == max)
+            .then(|| max)
+            .unwrap_or(0.0);
+
+        let weights = self.weights;
+        weights.monotonicity * monotonicity
+            + weights.smoothness * smoothness
+            + weights.empty_cells * empty_cells
+            + weights.corner_max * corner_max
+    }
+}
+
+/// Whether the bot is playing the in-game board.
+#[derive(Default)]
+pub struct AutoPlay(pub bool);
+
+pub struct AutoPlayPlugin;
+
+impl Plugin for AutoPlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AutoPlay>()
+            .insert_resource(load_ai_config())
+            .add_system_set(
+                SystemSet::on_update(RunState::Playing)
+                    .with_system(auto_play),
+            );
+    }
+}
+
+/// Reads the bot's settings from `ai.ron` in the data directory, so
+/// they can be tuned without a rebuild.
+pub fn load_ai_config() -> AiConfig {
+    match persist::load::<AiConfig>(AI_CONFIG_FILE) {
+        Ok(Some(config)) => config,
+        Ok(None) => AiConfig::default(),
+        Err(error) => {
+            warn!("could not load the ai config: {}", error);
+            AiConfig::default()
+        }
+    }
+}
+
+/// Queues the bot's pick whenever the board has settled, so it reads
+/// the tiles only after the last move and spawn have landed.
+fn auto_play(
+    auto_play: Res<AutoPlay>,
+    config: Res<AiConfig>,
+    distribution: Res<TileDistribution>,
+    mut move_queue: ResMut<MoveQueue>,
+    animating: Query<
+        (),
+        Or<(
+            With<EasingComponent<Transform>>,
+            With<EasingChainComponent<Transform>>,
+        )>,
+    >,
+    tiles: Query<(&Position, &Points)>,
+    query_board: Query<&Board>,
+) {
+    if !auto_play.0 || !move_queue.is_settled() || !animating.is_empty() {
+        return;
+    }
+    let state = GameState::from_tiles(
+        query_board.single().size,
+        tiles.iter().map(|(pos, points)| ((pos.x, pos.y), points.value)),
+    );
+    if let Some(board_shift) = config.best_move(&state, &distribution) {
+        move_queue.push(board_shift);
+    }
+}
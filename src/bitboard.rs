@@ -0,0 +1,233 @@
+//! Compact boards for searching many moves ahead.
+//!
+//! `GameState` is what the ECS syncs from and what animations are
+//! built from; the bot doesn't need to know which tile went where,
+//! only what the board looks like afterwards. For the classic 4x4
+//! board that fits in a `u64` and a shift is four table lookups.
+
+use once_cell::sync::Lazy;
+
+use crate::rules::{BoardShift, GameState};
+
+const PACKED_SIZE: u8 = 4;
+/// The largest exponent a nibble can hold. Two of these don't merge
+/// on a packed board, which only a search past 32768 could notice.
+const MAX_RANK: u8 = 15;
+
+/// What the search needs from a board, whatever its layout. Tiles are
+/// handled as exponents: 0 is an empty cell, 11 is a 2048.
+pub trait SearchBoard: Clone {
+    fn size(&self) -> u8;
+
+    fn rank(&self, x: u8, y: u8) -> u8;
+
+    fn set_rank(&mut self, x: u8, y: u8, rank: u8);
+
+    /// The board after `board_shift`, or `None` if nothing moves.
+    fn shifted(&self, board_shift: BoardShift) -> Option<Self>;
+
+    fn empty_cells(&self) -> Vec<(u8, u8)>;
+}
+
+impl SearchBoard for GameState {
+    fn size(&self) -> u8 {
+        GameState::size(self)
+    }
+
+    fn rank(&self, x: u8, y: u8) -> u8 {
+        self.get(x, y)
+            .map_or(0, |value| value.trailing_zeros() as u8)
+    }
+
+    fn set_rank(&mut self, x: u8, y: u8, rank: u8) {
+        self.set(x, y, (rank > 0).then(|| 1 << rank));
+    }
+
+    fn shifted(&self, board_shift: BoardShift) -> Option<Self> {
+        let mut next = self.clone();
+        next.shift(board_shift).changed.then(|| next)
+    }
+
+    fn empty_cells(&self) -> Vec<(u8, u8)> {
+        GameState::empty_cells(self).collect()
+    }
+}
+
+/// Every possible 4-cell row shifted towards nibble 0 and towards
+/// nibble 3, indexed by the row itself.
+struct RowTables {
+    left: Vec<u16>,
+    right: Vec<u16>,
+}
+
+static ROW_TABLES: Lazy<RowTables> = Lazy::new(|| {
+    let left: Vec<u16> = (0..=u16::MAX).map(shift_row_left).collect();
+    let right = (0..=u16::MAX)
+        .map(|row| reverse_row(left[usize::from(reverse_row(row))]))
+        .collect();
+    RowTables { left, right }
+});
+
+fn reverse_row(row: u16) -> u16 {
+    (row >> 12) | ((row >> 4) & 0x00F0) | ((row << 4) & 0x0F00) | (row << 12)
+}
+
+/// The slide and merge rules from `GameState::shift`, on one row of
+/// exponents.
+fn shift_row_left(row: u16) -> u16 {
+    let ranks = (0..4)
+        .map(|i| ((row >> (4 * i)) & 0xF) as u8)
+        .filter(|rank| *rank != 0);
+
+    let mut result: Vec<u8> = Vec::with_capacity(4);
+    let mut merged = false;
+    for rank in ranks {
+        match result.last_mut() {
+            Some(last) if !merged && *last == rank && rank < MAX_RANK => {
+                *last += 1;
+                merged = true;
+            }
+            _ => {
+                result.push(rank);
+                merged = false;
+            }
+        }
+    }
+    result
+        .iter()
+        .enumerate()
+        .fold(0, |row, (i, rank)| row | u16::from(*rank) << (4 * i))
+}
+
+/// A 4x4 board with each cell's exponent in a nibble, laid out like
+/// `GameState`'s cells: nibble `y * 4 + x`, so each row is a `u16`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Bitboard(u64);
+
+impl Bitboard {
+    /// `None` for anything that doesn't fit: boards other than 4x4,
+    /// or tiles past 32768.
+    pub fn from_state(state: &GameState) -> Option<Self> {
+        if state.size() != PACKED_SIZE {
+            return None;
+        }
+        state.tiles().try_fold(Bitboard(0), |mut board, ((x, y), value)| {
+            let rank = value.trailing_zeros() as u8;
+            (rank <= MAX_RANK).then(|| {
+                board.set_rank(x, y, rank);
+                board
+            })
+        })
+    }
+
+    /// Swaps rows and columns, so a column can be shifted as a row.
+    fn transpose(self) -> Self {
+        let x = self.0;
+        let a1 = x & 0xF0F0_0F0F_F0F0_0F0F;
+        let a2 = x & 0x0000_F0F0_0000_F0F0;
+        let a3 = x & 0x0F0F_0000_0F0F_0000;
+        let a = a1 | (a2 << 12) | (a3 >> 12);
+        let b1 = a & 0xFF00_FF00_00FF_00FF;
+        let b2 = a & 0x00FF_00FF_0000_0000;
+        let b3 = a & 0x0000_0000_FF00_FF00;
+        Bitboard(b1 | (b2 >> 24) | (b3 << 24))
+    }
+
+    fn map_rows(self, table: &[u16]) -> Self {
+        Bitboard((0..4).fold(0, |board, row| {
+            let bits = (self.0 >> (16 * row)) as u16;
+            board | u64::from(table[usize::from(bits)]) << (16 * row)
+        }))
+    }
+}
+
+impl SearchBoard for Bitboard {
+    fn size(&self) -> u8 {
+        PACKED_SIZE
+    }
+
+    fn rank(&self, x: u8, y: u8) -> u8 {
+        ((self.0 >> (4 * (y * PACKED_SIZE + x))) & 0xF) as u8
+    }
+
+    fn set_rank(&mut self, x: u8, y: u8, rank: u8) {
+        let shift = 4 * (y * PACKED_SIZE + x);
+        self.0 = (self.0 & !(0xF << shift)) | u64::from(rank) << shift;
+    }
+
+    fn shifted(&self, board_shift: BoardShift) -> Option<Self> {
+        let tables = &*ROW_TABLES;
+        // after transposing, a column runs from y = 0 at nibble 0
+        let next = match board_shift {
+            BoardShift::Left => self.map_rows(&tables.left),
+            BoardShift::Right => self.map_rows(&tables.right),
+            BoardShift::Down => {
+                self.transpose().map_rows(&tables.left).transpose()
+            }
+            BoardShift::Up => {
+                self.transpose().map_rows(&tables.right).transpose()
+            }
+        };
+        (next != *self).then(|| next)
+    }
+
+    fn empty_cells(&self) -> Vec<(u8, u8)> {
+        (0..PACKED_SIZE)
+            .flat_map(|x| (0..PACKED_SIZE).map(move |y| (x, y)))
+            .filter(|(x, y)| self.rank(*x, *y) == 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// A 4x4 board with about half the cells filled, small enough
+    /// that no merge runs into `MAX_RANK`.
+    fn random_state(rng: &mut impl Rng) -> GameState {
+        let mut state = GameState::new(PACKED_SIZE);
+        for x in 0..PACKED_SIZE {
+            for y in 0..PACKED_SIZE {
+                if rng.gen_bool(0.5) {
+                    state.set(x, y, Some(1 << rng.gen_range(1..=11)));
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn shifted_matches_game_state_shift() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2048);
+        for _ in 0..2000 {
+            let state = random_state(&mut rng);
+            let board = Bitboard::from_state(&state)
+                .expect("a 4x4 board with small tiles should pack");
+            for board_shift in BoardShift::ALL {
+                let mut expected = state.clone();
+                let expected = expected
+                    .shift(board_shift)
+                    .changed
+                    .then(|| Bitboard::from_state(&expected).unwrap());
+                assert_eq!(
+                    board.shifted(board_shift),
+                    expected,
+                    "{:?} on\n{}",
+                    board_shift,
+                    state
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn from_state_rejects_what_does_not_pack() {
+        assert_eq!(Bitboard::from_state(&GameState::new(5)), None);
+        let huge = GameState::from_tiles(PACKED_SIZE, [((0, 0), 1 << 16)]);
+        assert_eq!(Bitboard::from_state(&huge), None);
+    }
+}
@@ -1,28 +1,285 @@
-use bevy::prelude::Color;
+//! Named color palettes. The built-in ones ship as files in
+//! `assets/themes`, and any other `*.theme.ron` or `*.theme.toml`
+//! dropped in there can be picked in the settings too. Edits to a
+//! theme file show up while the game is running.
 
-pub struct Materials {
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::GameSettings;
+
+const THEME_DIR: &str = "themes";
+
+/// In the order the settings cycle through them.
+pub const BUILT_IN_THEMES: [&str; 4] =
+    ["light", "dark", "classic", "high-contrast"];
+
+/// Colors are written as hex strings, `"bbada0"` or with alpha
+/// `"000000cc"`.
+#[derive(Debug, Clone, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "5b0c7e36-8f52-4b4e-9a1d-3f6b2a9c41d7"]
+pub struct Theme {
+    /// What the settings show and save.
+    pub name: String,
+    #[serde(deserialize_with = "hex")]
+    pub background: Color,
+    #[serde(deserialize_with = "hex")]
     pub board: Color,
+    #[serde(deserialize_with = "hex")]
     pub tile_placeholder: Color,
-    pub tile: Color,
-    pub none: Color,
+    /// Tile colors for 2, 4, 8, ... Anything bigger uses the last.
+    #[serde(deserialize_with = "hex_list")]
+    pub tiles: Vec<Color>,
+    #[serde(deserialize_with = "hex")]
+    pub tile_text_dark: Color,
+    #[serde(deserialize_with = "hex")]
+    pub tile_text_light: Color,
+    /// Labels, wherever they sit.
+    #[serde(deserialize_with = "hex")]
+    pub text: Color,
+    pub ui: UiColors,
+    pub buttons: ButtonColors,
 }
 
-pub const MATERIALS: Materials = Materials {
-    board: Color::rgb(0.7, 0.7, 0.8),
-    tile_placeholder: Color::rgb(0.75, 0.75, 0.9),
-    tile: Color::rgb(0.9, 0.9, 1.0),
-    none: Color::NONE,
-};
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UiColors {
+    /// Behind the score boxes.
+    #[serde(deserialize_with = "hex")]
+    pub panel: Color,
+    /// Behind full-screen menus.
+    #[serde(deserialize_with = "hex")]
+    pub overlay: Color,
+    /// Behind announcements the board should show through.
+    #[serde(deserialize_with = "hex")]
+    pub scrim: Color,
+}
 
-pub struct ButtonMaterials {
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ButtonColors {
+    #[serde(deserialize_with = "hex")]
     pub normal: Color,
+    #[serde(deserialize_with = "hex")]
     pub hovered: Color,
+    #[serde(deserialize_with = "hex")]
     pub pressed: Color,
+    #[serde(deserialize_with = "hex")]
+    pub text: Color,
+}
+
+fn parse_hex<E: de::Error>(value: &str) -> Result<Color, E> {
+    Color::hex(value.trim_start_matches('#')).map_err(|error| {
+        E::custom(format!("bad color {:?}: {:?}", value, error))
+    })
+}
+
+fn hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    parse_hex(&String::deserialize(deserializer)?)
+}
+
+fn hex_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Color>, D::Error> {
+    let values = Vec::<String>::deserialize(deserializer)?;
+    if values.is_empty() {
+        return Err(de::Error::custom("a theme needs at least one tile color"));
+    }
+    values.iter().map(|value| parse_hex(value)).collect()
+}
+
+/// The light theme, built in so there is something to draw with
+/// before the theme files have loaded.
+impl Default for Theme {
+    fn default() -> Self {
+        ron::from_str(include_str!("../assets/themes/light.theme.ron"))
+            .expect("the built-in light theme should parse")
+    }
+}
+
+impl Theme {
+    pub fn tile_color(&self, value: u32) -> Color {
+        // 2 is 2^1, so it sits at index 0
+        let index = value.max(2).trailing_zeros() as usize - 1;
+        self.tiles[index.min(self.tiles.len() - 1)]
+    }
+
+    /// Dark text on light tiles and light text on dark ones.
+    pub fn tile_text_color(&self, background: Color) -> Color {
+        let luminance = 0.2126 * background.r()
+            + 0.7152 * background.g()
+            + 0.0722 * background.b();
+        if luminance > 0.6 {
+            self.tile_text_dark
+        } else {
+            self.tile_text_light
+        }
+    }
+
+    pub fn button_color(&self, interaction: Interaction) -> Color {
+        match interaction {
+            Interaction::Clicked => self.buttons.pressed,
+            Interaction::Hovered => self.buttons.hovered,
+            Interaction::None => self.buttons.normal,
+        }
+    }
 }
 
-pub const BUTTON_MATERIALS: ButtonMaterials =
-    ButtonMaterials {
-        normal: Color::rgb(0.75, 0.75, 0.9),
-        hovered: Color::rgb(0.7, 0.7, 0.9),
-        pressed: Color::rgb(0.6, 0.6, 1.0),
-    };
\ No newline at end of file
+/// Which part of the theme a UI node or text is colored from.
+/// Buttons are recolored on their own, by `Interaction`.
+#[derive(Component, Clone, Copy)]
+pub enum ThemeColor {
+    Text,
+    ButtonText,
+    Panel,
+    Overlay,
+    Scrim,
+}
+
+/// Every theme the settings can pick from, built-ins first.
+#[derive(Default)]
+pub struct Themes(Vec<Handle<Theme>>);
+
+impl Themes {
+    /// Only the ones that have finished loading.
+    pub fn names(&self, assets: &Assets<Theme>) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|handle| assets.get(handle))
+            .map(|theme| theme.name.clone())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct ThemeLoader;
+
+impl AssetLoader for ThemeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let is_toml = load_context
+                .path()
+                .extension()
+                .map_or(false, |extension| extension == "toml");
+            let theme: Theme = if is_toml {
+                toml::from_slice(bytes)?
+            } else {
+                ron::de::from_bytes(bytes)?
+            };
+            load_context.set_default_asset(LoadedAsset::new(theme));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["theme.ron", "theme.toml"]
+    }
+}
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Theme>()
+            .init_asset_loader::<ThemeLoader>()
+            .init_resource::<Theme>()
+            .init_resource::<Themes>()
+            .add_startup_system(load_themes)
+            .add_system(select_theme)
+            // after `Update`, so whatever was spawned this frame is
+            // never drawn in the wrong colors
+            .add_system_to_stage(CoreStage::PostUpdate, apply_theme_colors);
+    }
+}
+
+fn load_themes(asset_server: Res<AssetServer>, mut themes: ResMut<Themes>) {
+    for name in BUILT_IN_THEMES {
+        let path = format!("{}/{}.theme.ron", THEME_DIR, name);
+        themes.0.push(asset_server.load(path.as_str()));
+    }
+    match asset_server.load_folder(THEME_DIR) {
+        Ok(handles) => {
+            for handle in handles {
+                let handle = handle.typed::<Theme>();
+                if !themes.0.contains(&handle) {
+                    themes.0.push(handle);
+                }
+            }
+        }
+        Err(error) => warn!("could not look for more themes: {:?}", error),
+    }
+}
+
+/// Swaps in the theme named in the settings, when it's picked and
+/// whenever its file is loaded or edited. An unknown name keeps
+/// whatever is showing.
+fn select_theme(
+    mut theme_events: EventReader<AssetEvent<Theme>>,
+    settings: Res<GameSettings>,
+    assets: Res<Assets<Theme>>,
+    mut theme: ResMut<Theme>,
+) {
+    if theme_events.iter().last().is_none() && !settings.is_changed() {
+        return;
+    }
+    let selected = assets
+        .iter()
+        .map(|(_, theme)| theme)
+        .find(|candidate| candidate.name == settings.theme);
+    if let Some(selected) = selected {
+        if *selected != *theme {
+            *theme = selected.clone();
+        }
+    }
+}
+
+fn apply_theme_colors(
+    theme: Res<Theme>,
+    mut themed: Query<
+        (
+            &ThemeColor,
+            ChangeTrackers<ThemeColor>,
+            Option<&mut UiColor>,
+            Option<&mut Text>,
+        ),
+        Without<Button>,
+    >,
+    mut buttons: Query<
+        (&Interaction, ChangeTrackers<Button>, &mut UiColor),
+        With<Button>,
+    >,
+) {
+    for (theme_color, tracker, ui_color, text) in themed.iter_mut() {
+        if !theme.is_changed() && !tracker.is_added() {
+            continue;
+        }
+        let color = match theme_color {
+            ThemeColor::Text => theme.text,
+            ThemeColor::ButtonText => theme.buttons.text,
+            ThemeColor::Panel => theme.ui.panel,
+            ThemeColor::Overlay => theme.ui.overlay,
+            ThemeColor::Scrim => theme.ui.scrim,
+        };
+        if let Some(mut ui_color) = ui_color {
+            ui_color.0 = color;
+        }
+        if let Some(mut text) = text {
+            for section in text.sections.iter_mut() {
+                section.style.color = color;
+            }
+        }
+    }
+
+    for (interaction, tracker, mut ui_color) in buttons.iter_mut() {
+        if theme.is_changed() || tracker.is_added() {
+            ui_color.0 = theme.button_color(*interaction);
+        }
+    }
+}
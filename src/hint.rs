@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+
+use crate::{
+    ai::AiConfig,
+    rules::{BoardShift, GameState, TileDistribution},
+    Board, FontSpec, Game, GameSettings, Points, Position, RunState,
+};
+
+/// Gap between the board's edge and a direction's expected score.
+const HINT_SCORE_MARGIN: f32 = 30.0;
+
+/// Asks for the bot's pick on the current board.
+pub struct HintRequest;
+
+/// Everything drawn over the board for the current hint. It stays up
+/// until the tiles next change.
+#[derive(Component)]
+pub struct HintOverlay;
+
+pub struct HintPlugin;
+
+impl Plugin for HintPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<HintRequest>()
+            .add_system(clear_hint)
+            .add_system_set(
+                SystemSet::on_update(RunState::Playing)
+                    .with_system(show_hint.after(clear_hint)),
+            );
+    }
+}
+
+fn arrow(board_shift: BoardShift) -> &'static str {
+    match board_shift {
+        BoardShift::Left => "\u{2190}",
+        BoardShift::Right => "\u{2192}",
+        BoardShift::Up => "\u{2191}",
+        BoardShift::Down => "\u{2193}",
+    }
+}
+
+fn centered_text(value: String, font: Handle<Font>, font_size: f32) -> Text {
+    Text::from_section(
+        value,
+        TextStyle {
+            font,
+            font_size,
+            color: Color::rgba(1.0, 1.0, 1.0, 0.85),
+        },
+    )
+    .with_alignment(TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    })
+}
+
+/// Asking again on the same board shows the same hint, so it only
+/// counts once; `last_counted` is the board the last counted one was on.
+fn show_hint(
+    mut commands: Commands,
+    mut last_counted: Local<Option<GameState>>,
+    mut hint_reader: EventReader<HintRequest>,
+    config: Res<AiConfig>,
+    distribution: Res<TileDistribution>,
+    settings: Res<GameSettings>,
+    font_spec: Res<FontSpec>,
+    mut game: ResMut<Game>,
+    query_board: Query<&Board>,
+    tiles: Query<(&Position, &Points)>,
+    overlays: Query<Entity, With<HintOverlay>>,
+) {
+    if hint_reader.iter().last().is_none() {
+        return;
+    }
+    let board = query_board.single();
+    let state = GameState::from_tiles(
+        board.size,
+        tiles.iter().map(|(pos, points)| ((pos.x, pos.y), points.value)),
+    );
+    let scores = config.evaluate_moves(&state, &distribution);
+    let best = match scores
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    {
+        Some((board_shift, _)) => *board_shift,
+        None => return,
+    };
+
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if last_counted.as_ref() != Some(&state) {
+        game.hints_used += 1;
+        *last_counted = Some(state);
+    }
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: centered_text(
+                arrow(best).to_string(),
+                font_spec.family.clone(),
+                board.physical_size * 0.4,
+            ),
+            transform: Transform::from_xyz(0.0, 0.0, 10.0),
+            ..Default::default()
+        })
+        .insert(HintOverlay);
+
+    if !settings.hint_scores {
+        return;
+    }
+    let edge = board.physical_size / 2.0 + HINT_SCORE_MARGIN;
+    for (board_shift, score) in scores {
+        let (x, y) = match board_shift {
+            BoardShift::Left => (-edge, 0.0),
+            BoardShift::Right => (edge, 0.0),
+            BoardShift::Up => (0.0, edge),
+            BoardShift::Down => (0.0, -edge),
+        };
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: centered_text(
+                    format!("{:.0}", score),
+                    font_spec.family.clone(),
+                    20.0,
+                ),
+                transform: Transform::from_xyz(x, y, 10.0),
+                ..Default::default()
+            })
+            .insert(HintOverlay);
+    }
+}
+
+/// A hint only holds for the board it was asked on, so any move,
+/// undo, new game or resize takes it down.
+fn clear_hint(
+    mut commands: Commands,
+    changed_tiles: Query<(), Or<(Changed<Position>, Changed<Points>)>>,
+    changed_board: Query<(), Changed<Board>>,
+    overlays: Query<Entity, With<HintOverlay>>,
+) {
+    if changed_tiles.is_empty() && changed_board.is_empty() {
+        return;
+    }
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
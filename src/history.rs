@@ -0,0 +1,180 @@
+use bevy::prelude::*;
+
+use crate::{
+    board_shift,
+    input::MoveQueue,
+    replay::ReplayRecorder,
+    rng::{GameRng, RngState},
+    rules::GameState,
+    spawn_tile, Board, FontSpec, Game, GameSettings, Points,
+    Position, RunState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryAction {
+    Undo,
+    Redo,
+}
+
+/// The board as it was before a move, along with everything needed
+/// to play on from there exactly as before.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    state: GameState,
+    score: u32,
+    moves: u32,
+    merges: u32,
+    /// Undoing the winning move takes the win back, so merging the
+    /// tile again shows the overlay again.
+    won: bool,
+    rng: RngState,
+}
+
+impl Snapshot {
+    pub fn capture(state: GameState, game: &Game, rng: &GameRng) -> Self {
+        Snapshot {
+            state,
+            score: game.score,
+            moves: game.moves,
+            merges: game.merges,
+            won: game.won,
+            rng: rng.state(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UndoHistory {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    undos_used: u32,
+}
+
+impl UndoHistory {
+    /// Called before every move that changes the board. A new move
+    /// forks history, so anything that could be redone is dropped.
+    pub fn record(&mut self, snapshot: Snapshot) {
+        self.undo.push(snapshot);
+        self.redo.clear();
+    }
+
+    /// `budget` caps the undos for a whole game; `None` is unlimited.
+    pub fn can_undo(&self, budget: Option<u32>) -> bool {
+        !self.undo.is_empty()
+            && budget.map_or(true, |budget| self.undos_used < budget)
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UndoHistory>()
+            .add_event::<HistoryAction>()
+            .add_system_set(
+                SystemSet::on_update(RunState::Playing)
+                    .with_system(apply_history.after(board_shift)),
+            )
+            // the game-over screen can take back the move that ended
+            // the game
+            .add_system_set(
+                SystemSet::on_update(RunState::GameOver)
+                    .with_system(apply_history),
+            )
+            .add_system_set(
+                SystemSet::on_enter(RunState::Playing)
+                    .with_system(clear_history),
+            );
+    }
+}
+
+fn apply_history(
+    mut commands: Commands,
+    mut history_reader: EventReader<HistoryAction>,
+    mut history: ResMut<UndoHistory>,
+    settings: Res<GameSettings>,
+    mut game: ResMut<Game>,
+    mut rng: ResMut<GameRng>,
+    mut recorder: ResMut<ReplayRecorder>,
+    query_board: Query<&Board>,
+    font_spec: Res<FontSpec>,
+    tiles: Query<(Entity, &Position, &Points)>,
+    move_queue: Res<MoveQueue>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    // the tiles query is stale once we respawn, so only the latest
+    // request of the frame is honoured
+    let action = match history_reader.iter().last() {
+        Some(action) => *action,
+        None => return,
+    };
+    // a move still landing has a tile yet to spawn, which would land
+    // on the restored board; `GameOver` only comes after that spawn
+    if *run_state.current() == RunState::Playing && !move_queue.is_settled()
+    {
+        return;
+    }
+    let board = query_board.single();
+    let current = Snapshot::capture(
+        GameState::from_tiles(
+            board.size,
+            tiles
+                .iter()
+                .map(|(_, pos, points)| ((pos.x, pos.y), points.value)),
+        ),
+        &game,
+        &rng,
+    );
+
+    let target = match action {
+        HistoryAction::Undo => {
+            if !history.can_undo(settings.undo_budget) {
+                return;
+            }
+            history.undos_used += 1;
+            recorder.undo();
+            history.redo.push(current);
+            history.undo.pop()
+        }
+        HistoryAction::Redo => {
+            if !history.can_redo() {
+                return;
+            }
+            recorder.redo();
+            history.undo.push(current);
+            history.redo.pop()
+        }
+    }
+    .expect("history should not be empty after checking it");
+
+    for (entity, _, _) in tiles.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for ((x, y), value) in target.state.tiles() {
+        spawn_tile(
+            &mut commands,
+            board,
+            &font_spec,
+            Position { x, y },
+            value,
+        );
+    }
+    game.score = target.score;
+    game.moves = target.moves;
+    game.merges = target.merges;
+    game.won = target.won;
+    *rng = GameRng::from_state(target.rng);
+
+    // `Playing` waits underneath with everything but the tiles intact
+    if *run_state.current() == RunState::GameOver {
+        let _ = run_state.pop();
+    }
+}
+
+fn clear_history(mut history: ResMut<UndoHistory>) {
+    *history = UndoHistory::default();
+}
@@ -0,0 +1,779 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::AutoPlay,
+    colors::Theme, hint::HintRequest, history::HistoryAction,
+    persist,
+    rules::BoardShift, GameSettings, RunState, SLIDE_DURATION_MS,
+};
+
+pub const MOVE_QUEUE_DEPTH: usize = 4;
+const KEY_BINDINGS_FILE: &str = "key_bindings.ron";
+/// How far the stick has to lean before it counts as a press.
+const STICK_DEADZONE: f32 = 0.5;
+/// How close to the centre it has to come back before it can fire
+/// again; the gap to `STICK_DEADZONE` keeps a wobbly stick from
+/// repeating.
+const STICK_RELEASE: f32 = 0.3;
+
+/// A drag shorter than this, in logical pixels, is a click.
+const SWIPE_MIN_DISTANCE: f32 = 40.0;
+/// A drag slower than this, in pixels per second, is someone
+/// changing their mind rather than swiping.
+const SWIPE_MIN_VELOCITY: f32 = 200.0;
+
+const GAMEPAD_DPAD: [(GamepadButtonType, BoardShift); 4] = [
+    (GamepadButtonType::DPadLeft, BoardShift::Left),
+    (GamepadButtonType::DPadRight, BoardShift::Right),
+    (GamepadButtonType::DPadUp, BoardShift::Up),
+    (GamepadButtonType::DPadDown, BoardShift::Down),
+];
+
+const GAMEPAD_ACTIONS: [(GamepadButtonType, Action); 3] = [
+    (GamepadButtonType::West, Action::Undo),
+    (GamepadButtonType::North, Action::Restart),
+    (GamepadButtonType::Start, Action::Pause),
+];
+
+/// Everything a key can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Shift(BoardShift),
+    Undo,
+    Redo,
+    Restart,
+    Pause,
+    AutoPlay,
+    Hint,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::Shift(BoardShift::Left),
+        Action::Shift(BoardShift::Right),
+        Action::Shift(BoardShift::Up),
+        Action::Shift(BoardShift::Down),
+        Action::Undo,
+        Action::Redo,
+        Action::Restart,
+        Action::Pause,
+        Action::AutoPlay,
+        Action::Hint,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Shift(BoardShift::Left) => "Left",
+            Action::Shift(BoardShift::Right) => "Right",
+            Action::Shift(BoardShift::Up) => "Up",
+            Action::Shift(BoardShift::Down) => "Down",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::Restart => "Restart",
+            Action::Pause => "Pause",
+            Action::AutoPlay => "Auto play",
+            Action::Hint => "Hint",
+        }
+    }
+}
+
+/// A key plus the modifiers that have to be held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyChord {
+    pub key: KeyCode,
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub shift: bool,
+}
+
+impl KeyChord {
+    pub fn key(key: KeyCode) -> Self {
+        KeyChord {
+            key,
+            ctrl: false,
+            shift: false,
+        }
+    }
+
+    pub fn ctrl(key: KeyCode) -> Self {
+        KeyChord {
+            ctrl: true,
+            ..KeyChord::key(key)
+        }
+    }
+
+    pub fn shift(key: KeyCode) -> Self {
+        KeyChord {
+            shift: true,
+            ..KeyChord::key(key)
+        }
+    }
+
+    fn modifier_count(&self) -> u8 {
+        u8::from(self.ctrl) + u8::from(self.shift)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingPreset {
+    Arrows,
+    Wasd,
+    Hjkl,
+}
+
+impl BindingPreset {
+    pub const ALL: [BindingPreset; 3] = [
+        BindingPreset::Arrows,
+        BindingPreset::Wasd,
+        BindingPreset::Hjkl,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BindingPreset::Arrows => "Arrows",
+            BindingPreset::Wasd => "WASD",
+            BindingPreset::Hjkl => "HJKL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub bindings: Vec<(KeyChord, Action)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings::preset(BindingPreset::Arrows)
+    }
+}
+
+impl KeyBindings {
+    pub fn preset(preset: BindingPreset) -> Self {
+        let (left, right, up, down) = match preset {
+            BindingPreset::Arrows => {
+                (KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down)
+            }
+            BindingPreset::Wasd => {
+                (KeyCode::A, KeyCode::D, KeyCode::W, KeyCode::S)
+            }
+            BindingPreset::Hjkl => {
+                (KeyCode::H, KeyCode::L, KeyCode::K, KeyCode::J)
+            }
+        };
+        let redo = match preset {
+            // vim's own redo
+            BindingPreset::Hjkl => KeyChord::ctrl(KeyCode::R),
+            _ => KeyChord::shift(KeyCode::U),
+        };
+        KeyBindings {
+            bindings: vec![
+                (KeyChord::key(left), Action::Shift(BoardShift::Left)),
+                (KeyChord::key(right), Action::Shift(BoardShift::Right)),
+                (KeyChord::key(up), Action::Shift(BoardShift::Up)),
+                (KeyChord::key(down), Action::Shift(BoardShift::Down)),
+                (KeyChord::key(KeyCode::U), Action::Undo),
+                (KeyChord::ctrl(KeyCode::Z), Action::Undo),
+                (redo, Action::Redo),
+                (KeyChord::ctrl(KeyCode::Y), Action::Redo),
+                (KeyChord::key(KeyCode::R), Action::Restart),
+                (KeyChord::key(KeyCode::Escape), Action::Pause),
+                (KeyChord::key(KeyCode::P), Action::Pause),
+                (KeyChord::key(KeyCode::Tab), Action::AutoPlay),
+                (KeyChord::key(KeyCode::Space), Action::Hint),
+            ],
+        }
+    }
+
+    pub fn chords_for(
+        &self,
+        action: Action,
+    ) -> impl Iterator<Item = &KeyChord> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == action)
+            .map(|(chord, _)| chord)
+    }
+
+    /// A chord only belongs to one action, so binding it again moves
+    /// it rather than doubling it up.
+    pub fn bind(&mut self, chord: KeyChord, action: Action) {
+        self.bindings.retain(|(bound, _)| *bound != chord);
+        self.bindings.push((chord, action));
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.retain(|(_, bound)| *bound != action);
+    }
+
+    /// The actions triggered this frame, with the key behind each.
+    /// When several chords share a key, only the one with the most
+    /// modifiers held counts, so Shift+U doesn't also fire the plain U
+    /// binding.
+    pub fn just_pressed(
+        &self,
+        keyboard_input: &Input<KeyCode>,
+    ) -> Vec<(KeyCode, Action)> {
+        let ctrl = keyboard_input.pressed(KeyCode::LControl)
+            || keyboard_input.pressed(KeyCode::RControl);
+        let shift = keyboard_input.pressed(KeyCode::LShift)
+            || keyboard_input.pressed(KeyCode::RShift);
+
+        keyboard_input
+            .get_just_pressed()
+            .filter_map(|key| {
+                self.bindings
+                    .iter()
+                    .filter(|(chord, _)| {
+                        chord.key == *key
+                            && (!chord.ctrl || ctrl)
+                            && (!chord.shift || shift)
+                    })
+                    .max_by_key(|(chord, _)| chord.modifier_count())
+                    .map(|(_, action)| (*key, *action))
+            })
+            .collect()
+    }
+}
+
+/// Moves waiting for the board to settle.
+///
+/// Input is captured the frame it happens, but a move is only handed
+/// out once the tiles from the previous one have stopped animating.
+/// Anything past `max_depth` is dropped so mashing a key can't queue
+/// up seconds of moves. The depth comes from `GameSettings`.
+pub struct MoveQueue {
+    moves: VecDeque<BoardShift>,
+    max_depth: usize,
+    /// Covers the frames between a move and its eases being attached,
+    /// when the board looks settled but isn't.
+    cooldown: Timer,
+}
+
+impl Default for MoveQueue {
+    fn default() -> Self {
+        let duration = Duration::from_millis(SLIDE_DURATION_MS);
+        let mut cooldown = Timer::new(duration, false);
+        cooldown.tick(duration);
+        MoveQueue {
+            moves: VecDeque::new(),
+            max_depth: MOVE_QUEUE_DEPTH,
+            cooldown,
+        }
+    }
+}
+
+impl MoveQueue {
+    pub fn push(&mut self, board_shift: BoardShift) {
+        if self.moves.len() < self.max_depth {
+            self.moves.push_back(board_shift);
+        }
+    }
+
+    /// The next move to play, if there is one and the board has
+    /// settled.
+    pub fn next_move(
+        &mut self,
+        delta: Duration,
+        animating: bool,
+    ) -> Option<BoardShift> {
+        self.cooldown.tick(delta);
+        if animating || !self.cooldown.finished() {
+            return None;
+        }
+        self.moves.pop_front()
+    }
+
+    /// Nothing queued and the last move has had time to land.
+    pub fn is_settled(&self) -> bool {
+        self.moves.is_empty() && self.cooldown.finished()
+    }
+
+    /// Holds the next move back for `duration`, the length of the
+    /// slide just started.
+    pub fn start_cooldown(&mut self, duration: Duration) {
+        self.cooldown.set_duration(duration);
+        self.cooldown.reset();
+    }
+
+    pub fn clear(&mut self) {
+        self.moves.clear();
+    }
+}
+
+/// Turns the left stick of each gamepad into single presses.
+#[derive(Default)]
+pub struct StickLatch(HashMap<Gamepad, bool>);
+
+impl StickLatch {
+    fn shift(
+        &mut self,
+        gamepad: Gamepad,
+        axes: &Axis<GamepadAxis>,
+    ) -> Option<BoardShift> {
+        let x = axes
+            .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        let y = axes
+            .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))
+            .unwrap_or(0.0);
+        let magnitude = x.abs().max(y.abs());
+
+        let latched = self.0.entry(gamepad).or_insert(false);
+        if *latched {
+            if magnitude < STICK_RELEASE {
+                *latched = false;
+            }
+            return None;
+        }
+        if magnitude < STICK_DEADZONE {
+            return None;
+        }
+        *latched = true;
+        Some(if x.abs() > y.abs() {
+            if x > 0.0 {
+                BoardShift::Right
+            } else {
+                BoardShift::Left
+            }
+        } else if y > 0.0 {
+            BoardShift::Up
+        } else {
+            BoardShift::Down
+        })
+    }
+}
+
+fn gamepad_shift(
+    gamepad: Gamepad,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+    latch: &mut StickLatch,
+) -> Option<BoardShift> {
+    GAMEPAD_DPAD
+        .iter()
+        .find(|(button_type, _)| {
+            buttons.just_pressed(GamepadButton(gamepad, *button_type))
+        })
+        .map(|(_, board_shift)| *board_shift)
+        .or_else(|| latch.shift(gamepad, axes))
+}
+
+/// Picks a direction from a drag, or nothing if it was too short or
+/// too slow to be a swipe. `delta` is y-up like the board.
+fn swipe_shift(delta: Vec2, seconds: f32) -> Option<BoardShift> {
+    let distance = delta.length();
+    if distance < SWIPE_MIN_DISTANCE
+        || distance < SWIPE_MIN_VELOCITY * seconds
+    {
+        return None;
+    }
+    Some(if delta.x.abs() > delta.y.abs() {
+        if delta.x > 0.0 {
+            BoardShift::Right
+        } else {
+            BoardShift::Left
+        }
+    } else if delta.y > 0.0 {
+        BoardShift::Up
+    } else {
+        BoardShift::Down
+    })
+}
+
+/// Bevy 0.8 passes touch positions on y-down, as winit reports them,
+/// except on Android and iOS where it flips them itself. The cursor is
+/// always y-up, so desktop touches are flipped to match.
+fn touch_position(position: Vec2, window_height: f32) -> Vec2 {
+    if cfg!(any(target_os = "android", target_os = "ios")) {
+        position
+    } else {
+        Vec2::new(position.x, window_height - position.y)
+    }
+}
+
+/// Where and when each drag in progress started. Mouse drags are
+/// keyed by `None`, touches by their id.
+#[derive(Default)]
+pub struct SwipeStarts(HashMap<Option<u64>, (Vec2, f64)>);
+
+/// The button a gamepad is pointing at while a menu is up.
+#[derive(Component)]
+pub struct GamepadFocus;
+
+pub struct MoveInputPlugin;
+
+impl Plugin for MoveInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MoveQueue>()
+            .init_resource::<KeyBindings>()
+            .add_startup_system(load_key_bindings)
+            .add_system_set(
+                SystemSet::on_update(RunState::Playing)
+                    .with_system(keyboard_actions)
+                    .with_system(gamepad_actions)
+                    .with_system(swipe_actions),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Paused)
+                    .with_system(resume_input),
+            )
+            .add_system(gamepad_menu_navigation)
+            .add_system(apply_move_queue_depth)
+            .add_system_set(
+                SystemSet::on_enter(RunState::Playing)
+                    .with_system(clear_move_queue),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Settings)
+                    .with_system(save_key_bindings),
+            );
+    }
+}
+
+/// Everything an `Action` can act on.
+#[derive(SystemParam)]
+pub struct ActionTargets<'w, 's> {
+    move_queue: ResMut<'w, MoveQueue>,
+    history_writer: EventWriter<'w, 's, HistoryAction>,
+    hint_writer: EventWriter<'w, 's, HintRequest>,
+    run_state: ResMut<'w, State<RunState>>,
+    auto_play: ResMut<'w, AutoPlay>,
+}
+
+impl<'w, 's> ActionTargets<'w, 's> {
+    /// Plays a single action from any input device. Returns `true`
+    /// once the run state is changing, after which the rest of this
+    /// frame's input is moot.
+    fn apply(&mut self, action: Action) -> bool {
+        match action {
+            Action::Shift(board_shift) => self.move_queue.push(board_shift),
+            Action::Undo => self.history_writer.send(HistoryAction::Undo),
+            Action::Redo => self.history_writer.send(HistoryAction::Redo),
+            Action::Restart => {
+                let _ = self.run_state.restart();
+                return true;
+            }
+            Action::Pause => {
+                let _ = self.run_state.push(RunState::Paused);
+                return true;
+            }
+            Action::AutoPlay => self.auto_play.0 = !self.auto_play.0,
+            Action::Hint => self.hint_writer.send(HintRequest),
+        }
+        false
+    }
+}
+
+fn keyboard_actions(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut targets: ActionTargets,
+) {
+    let pressed = key_bindings.just_pressed(&keyboard_input);
+    for (_, action) in pressed.iter() {
+        if targets.apply(*action) {
+            // the state driver runs this again within the frame, and
+            // the keys would still read as just pressed
+            for (key, _) in pressed.iter() {
+                keyboard_input.reset(*key);
+            }
+            return;
+        }
+    }
+}
+
+fn gamepad_actions(
+    gamepads: Res<Gamepads>,
+    mut buttons: ResMut<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut latch: Local<StickLatch>,
+    mut targets: ActionTargets,
+) {
+    for gamepad in gamepads.iter() {
+        if let Some(board_shift) =
+            gamepad_shift(*gamepad, &buttons, &axes, &mut latch)
+        {
+            targets.apply(Action::Shift(board_shift));
+        }
+
+        let pressed: Vec<(GamepadButton, Action)> = GAMEPAD_ACTIONS
+            .iter()
+            .map(|(button_type, action)| {
+                (GamepadButton(*gamepad, *button_type), *action)
+            })
+            .filter(|(button, _)| buttons.just_pressed(*button))
+            .collect();
+        for (_, action) in pressed.iter() {
+            if targets.apply(*action) {
+                // same as `keyboard_actions`: don't let the rerun
+                // within this frame see them again
+                for (button, _) in pressed.iter() {
+                    buttons.reset(*button);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn swipe_actions(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    mouse_input: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    mut starts: Local<SwipeStarts>,
+    mut targets: ActionTargets,
+) {
+    let now = time.seconds_since_startup();
+    let window = windows.get_primary();
+    let cursor = window.and_then(|window| window.cursor_position());
+    let window_height = window.map_or(0.0, |window| window.height());
+
+    if let Some(cursor) = cursor {
+        if mouse_input.just_pressed(MouseButton::Left) {
+            starts.0.insert(None, (cursor, now));
+        }
+    }
+    for touch in touches.iter_just_pressed() {
+        let start = touch_position(touch.position(), window_height);
+        starts.0.insert(Some(touch.id()), (start, now));
+    }
+
+    let mut ended: Vec<(Option<u64>, Vec2)> = touches
+        .iter_just_released()
+        .map(|touch| {
+            let end = touch_position(touch.position(), window_height);
+            (Some(touch.id()), end)
+        })
+        .collect();
+    if mouse_input.just_released(MouseButton::Left) {
+        match cursor {
+            Some(cursor) => ended.push((None, cursor)),
+            // released outside the window; nothing to measure
+            None => {
+                starts.0.remove(&None);
+            }
+        }
+    }
+    for touch in touches.iter_just_cancelled() {
+        starts.0.remove(&Some(touch.id()));
+    }
+
+    for (id, end) in ended {
+        let (start, started_at) = match starts.0.remove(&id) {
+            Some(start) => start,
+            None => continue,
+        };
+        let seconds = (now - started_at) as f32;
+        if let Some(board_shift) = swipe_shift(end - start, seconds) {
+            if targets.apply(Action::Shift(board_shift)) {
+                return;
+            }
+        }
+    }
+}
+
+fn resume_input(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    gamepads: Res<Gamepads>,
+    mut buttons: ResMut<Input<GamepadButton>>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    let start = gamepads
+        .iter()
+        .map(|gamepad| GamepadButton(*gamepad, GamepadButtonType::Start))
+        .find(|button| buttons.just_pressed(*button));
+    let key = key_bindings
+        .just_pressed(&keyboard_input)
+        .into_iter()
+        .find(|(_, action)| *action == Action::Pause)
+        .map(|(key, _)| key);
+    if start.is_none() && key.is_none() {
+        return;
+    }
+
+    let _ = run_state.pop();
+    // otherwise `Playing` reads the same press within this frame and
+    // pauses all over again
+    if let Some(button) = start {
+        buttons.reset(button);
+    }
+    if let Some(key) = key {
+        keyboard_input.reset(key);
+    }
+}
+
+/// Lets a gamepad walk the on-screen buttons whenever the board
+/// isn't taking moves: the D-pad or stick steps through them in the
+/// order they were spawned and South presses the focused one.
+fn gamepad_menu_navigation(
+    mut commands: Commands,
+    run_state: Res<State<RunState>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut latch: Local<StickLatch>,
+    mut pressed: Local<Option<Entity>>,
+    theme: Res<Theme>,
+    mut ui_buttons: Query<
+        (
+            Entity,
+            &mut Interaction,
+            &mut UiColor,
+            Option<&GamepadFocus>,
+        ),
+        With<Button>,
+    >,
+) {
+    // a click we faked last frame is over now
+    if let Some(entity) = pressed.take() {
+        if let Ok((_, mut interaction, _, _)) = ui_buttons.get_mut(entity)
+        {
+            if *interaction == Interaction::Clicked {
+                *interaction = Interaction::None;
+            }
+        }
+    }
+    if *run_state.current() == RunState::Playing {
+        return;
+    }
+
+    let mut step: i32 = 0;
+    let mut activate = false;
+    for gamepad in gamepads.iter() {
+        match gamepad_shift(*gamepad, &buttons, &axes, &mut latch) {
+            Some(BoardShift::Left | BoardShift::Up) => step = -1,
+            Some(BoardShift::Right | BoardShift::Down) => step = 1,
+            None => {}
+        }
+        activate |= buttons
+            .just_pressed(GamepadButton(*gamepad, GamepadButtonType::South));
+    }
+    if step == 0 && !activate {
+        return;
+    }
+
+    let mut order: Vec<(Entity, bool)> = ui_buttons
+        .iter()
+        .map(|(entity, _, _, focus)| (entity, focus.is_some()))
+        .collect();
+    if order.is_empty() {
+        return;
+    }
+    order.sort_by_key(|(entity, _)| entity.id());
+
+    let current = order.iter().position(|(_, focused)| *focused);
+    let next = match current {
+        Some(index) => (index as i32 + step)
+            .rem_euclid(order.len() as i32)
+            as usize,
+        // the first press only lands on the first button
+        None => 0,
+    };
+
+    if let Some(index) = current {
+        let entity = order[index].0;
+        commands.entity(entity).remove::<GamepadFocus>();
+        let (_, _, mut color, _) = ui_buttons.get_mut(entity).unwrap();
+        *color = theme.buttons.normal.into();
+    }
+    let entity = order[next].0;
+    commands.entity(entity).insert(GamepadFocus);
+    let (_, mut interaction, mut color, _) =
+        ui_buttons.get_mut(entity).unwrap();
+    *color = theme.buttons.hovered.into();
+
+    if activate && current.is_some() {
+        *interaction = Interaction::Clicked;
+        *pressed = Some(entity);
+    }
+}
+
+fn clear_move_queue(mut move_queue: ResMut<MoveQueue>) {
+    move_queue.clear();
+}
+
+/// Picks the depth up from the settings once they're loaded and
+/// whenever they change. Moves already waiting are kept.
+fn apply_move_queue_depth(
+    settings: Res<GameSettings>,
+    mut move_queue: ResMut<MoveQueue>,
+) {
+    if settings.is_changed() {
+        // a depth of 0 would drop every move
+        move_queue.max_depth = settings.move_queue_depth.max(1);
+    }
+}
+
+fn load_key_bindings(mut key_bindings: ResMut<KeyBindings>) {
+    match persist::load::<KeyBindings>(KEY_BINDINGS_FILE) {
+        Ok(Some(loaded)) => *key_bindings = loaded,
+        Ok(None) => {}
+        Err(error) => warn!("could not load key bindings: {}", error),
+    }
+}
+
+fn save_key_bindings(key_bindings: Res<KeyBindings>) {
+    if let Err(error) = persist::save(KEY_BINDINGS_FILE, &*key_bindings)
+    {
+        warn!("could not save key bindings: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swipe_picks_the_longer_axis() {
+        let start = Vec2::new(100.0, 100.0);
+        for (end, board_shift) in [
+            (Vec2::new(200.0, 120.0), BoardShift::Right),
+            (Vec2::new(0.0, 80.0), BoardShift::Left),
+            (Vec2::new(110.0, 200.0), BoardShift::Up),
+            (Vec2::new(90.0, 0.0), BoardShift::Down),
+        ] {
+            assert_eq!(swipe_shift(end - start, 0.1), Some(board_shift));
+        }
+    }
+
+    #[test]
+    fn short_or_slow_drags_are_not_swipes() {
+        assert_eq!(swipe_shift(Vec2::new(10.0, 0.0), 0.01), None);
+        assert_eq!(swipe_shift(Vec2::new(100.0, 0.0), 2.0), None);
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn desktop_touches_are_flipped_to_y_up() {
+        let height = 600.0;
+        // a finger moving towards the top of the screen, in winit's
+        // y-down coordinates
+        let start = touch_position(Vec2::new(300.0, 500.0), height);
+        let end = touch_position(Vec2::new(300.0, 100.0), height);
+        assert_eq!(start, Vec2::new(300.0, 100.0));
+        assert_eq!(swipe_shift(end - start, 0.1), Some(BoardShift::Up));
+    }
+}
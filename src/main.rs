@@ -1,14 +1,16 @@
+// Bevy systems take their resources and queries as parameters, so
+// long parameter lists and nested query types come with the territory.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
 use std::{
-    cmp::Ordering,
-    collections::HashMap,
-    convert::{TryFrom, TryInto},
-    ops::Range,
+    collections::{BTreeMap, HashMap},
+    time::Duration,
 };
 
-use bevy::prelude::*;
+use bevy::{asset::AssetServerSettings, prelude::*, window::WindowResized};
 use bevy_easings::*;
 use itertools::Itertools;
-use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 mod ui;
 use ui::*;
@@ -16,13 +18,64 @@ use ui::*;
 mod colors;
 use colors::*;
 
-const TILE_SIZE: f32 = 40.0;
-const TILE_SPACER: f32 = 10.0;
+mod rules;
+use rules::*;
 
-#[derive(Component)]
+mod rng;
+use rng::*;
+
+mod persist;
+
+mod save;
+use save::*;
+
+mod history;
+use history::*;
+
+mod input;
+use input::*;
+
+mod menus;
+use menus::*;
+
+mod ai;
+use ai::*;
+
+mod bitboard;
+
+mod hint;
+use hint::*;
+
+mod replay;
+use replay::*;
+
+mod stats;
+use stats::*;
+
+mod sim;
+
+const TILE_SPACER_RATIO: f32 = 0.25;
+const BOARD_WINDOW_FRACTION: f32 = 0.7;
+const STARTING_TILES: usize = 2;
+const SLIDE_DURATION_MS: u64 = 100;
+const POP_DURATION_MS: u64 = 60;
+const POP_SCALE: f32 = 1.2;
+const GROW_DURATION_MS: u64 = 120;
+const MIN_BOARD_SIZE: u8 = 3;
+const MAX_BOARD_SIZE: u8 = 10;
+
+#[derive(Component, Clone)]
 struct Board {
     size: u8,
     physical_size: f32,
+    tile_size: f32,
+    tile_spacer: f32,
+}
+
+#[derive(Component)]
+struct TilePlaceholder {
+    x: u8,
+    y: u8,
 }
 
 #[derive(Debug, PartialEq, Component)]
@@ -39,6 +92,12 @@ struct Position {
 #[derive(Component)]
 struct TileText;
 
+/// A tile that merged into another. It has already lost its
+/// `Position` and `Points` and only lives on to slide under the
+/// survivor before it is despawned.
+#[derive(Component)]
+struct Consumed;
+
 struct FontSpec {
     family: Handle<Font>,
 }
@@ -56,170 +115,295 @@ impl FromWorld for FontSpec {
 }
 
 impl Board {
-    fn new(size: u8) -> Self {
-        let physical_size = f32::from(size) * TILE_SIZE
-            + f32::from(size + 1) * TILE_SPACER;
+    /// Builds a board that fills `max_physical_size`, shrinking the
+    /// tiles as the number of cells grows.
+    fn new(size: u8, max_physical_size: f32) -> Self {
+        let tile_size = max_physical_size
+            / (f32::from(size)
+                + f32::from(size + 1) * TILE_SPACER_RATIO);
+        let tile_spacer = tile_size * TILE_SPACER_RATIO;
+        let physical_size = f32::from(size) * tile_size
+            + f32::from(size + 1) * tile_spacer;
         Board {
             size,
             physical_size,
+            tile_size,
+            tile_spacer,
         }
     }
+    fn fit_to_window(size: u8, windows: &Windows) -> Self {
+        let extent = windows
+            .get_primary()
+            .map(|window| window.width().min(window.height()))
+            .unwrap_or(720.0);
+        Board::new(size, extent * BOARD_WINDOW_FRACTION)
+    }
     fn cell_position_to_physical(&self, pos: u8) -> f32 {
         let offset =
-            -self.physical_size / 2.0 + 0.5 * TILE_SIZE;
+            -self.physical_size / 2.0 + 0.5 * self.tile_size;
 
         offset
-            + f32::from(pos) * TILE_SIZE
-            + f32::from(pos + 1) * TILE_SPACER
+            + f32::from(pos) * self.tile_size
+            + f32::from(pos + 1) * self.tile_spacer
     }
+    fn tile_extent(&self) -> Vec2 {
+        Vec2::new(self.tile_size, self.tile_size)
+    }
+    /// Shrinks the text as the number of digits grows so that
+    /// five-digit values still fit inside their tile.
+    fn tile_font_size(&self, value: u32) -> f32 {
+        let digits = value.to_string().len() as f32;
+        self.tile_size * (0.55_f32).min(1.4 / digits)
+    }
+}
+
+struct NewTileEvent;
+
+/// Sent once, after the move and spawn that leave the board with no
+/// shift that changes it.
+pub struct GameOverEvent {
+    pub score: u32,
+    pub max_tile: u32,
+    pub moves: u32,
 }
 
-enum BoardShift {
-    Left,
-    Right,
-    Up,
-    Down,
+impl GameOverEvent {
+    fn check(state: &GameState, game: &Game) -> Option<Self> {
+        (!state.has_moves()).then(|| GameOverEvent {
+            score: game.score,
+            max_tile: state.tiles().map(|(_, value)| value).max().unwrap_or(0),
+            moves: game.moves,
+        })
+    }
 }
 
-impl TryFrom<&KeyCode> for BoardShift {
-    type Error = &'static str;
+const SETTINGS_FILE: &str = "settings.ron";
+/// Animation speed multipliers the settings screen steps through.
+const ANIMATION_SPEEDS: [f32; 4] = [0.5, 1.0, 2.0, 4.0];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct GameSettings {
+    /// The size of the next game; the board on screen keeps its own
+    /// until then.
+    board_size: u8,
+    win_target: u32,
+    /// Undos allowed per game; `None` is unlimited, `Some(0)` is
+    /// classic mode with no undo at all.
+    undo_budget: Option<u32>,
+    /// Show each direction's expected score alongside a hint's arrow.
+    hint_scores: bool,
+    /// A `Theme` by name.
+    theme: String,
+    /// Higher is faster; 2.0 plays every animation in half the time.
+    animation_speed: f32,
+    /// How many moves can wait for the board to settle before more
+    /// are dropped. Only set from the settings file.
+    move_queue_depth: usize,
+}
 
-    fn try_from(value: &KeyCode) -> Result<Self, Self::Error> {
-        match value {
-            KeyCode::Left => Ok(BoardShift::Left),
-            KeyCode::Right => Ok(BoardShift::Right),
-            KeyCode::Up => Ok(BoardShift::Up),
-            KeyCode::Down => Ok(BoardShift::Down),
-            _ => Err("not a valid board shift key")
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            board_size: 4,
+            win_target: 2048,
+            undo_budget: None,
+            hint_scores: false,
+            theme: BUILT_IN_THEMES[0].to_string(),
+            animation_speed: 1.0,
+            move_queue_depth: MOVE_QUEUE_DEPTH,
         }
     }
 }
 
-impl BoardShift {
-    fn sort(&self, a: &Position, b: &Position) -> Ordering {
-        match self {
-            BoardShift::Left => {
-                match Ord::cmp(&a.y, &b.y) {
-                    Ordering::Equal => { 
-                        Ord::cmp(&a.x, &b.x)
-                    }
-                    ordering => ordering,
-                }
-            },
-            BoardShift::Right => {
-                match Ord::cmp(&b.y, &a.y) {
-                    Ordering::Equal => { 
-                        Ord::cmp(&b.x, &a.x)
-                    }
-                    a => a,
-                }
-            },            
-            BoardShift::Up => match Ord::cmp(&b.x, &a.x) {
-                Ordering::Equal => { 
-                    Ord::cmp(&b.y, &a.y)
-                }
-                ordering => ordering,
-            },
-            BoardShift::Down => match Ord::cmp(&a.x, &b.x) {
-                Ordering::Equal => { 
-                    Ord::cmp(&a.y, &b.y)
-                }
-                ordering => ordering,
-            },
-        }
+impl GameSettings {
+    /// An animation's base duration scaled by the chosen speed.
+    fn animation(&self, millis: u64) -> Duration {
+        Duration::from_secs_f32(
+            millis as f32 / 1000.0 / self.animation_speed,
+        )
     }
-    fn set_column_position(
-        &self,
-        board_size: u8,
-        position: &mut Mut<Position>,
-        index: u8,
-    ) {
-        match self {
-            BoardShift::Left => {
-                position.x = index;
-                //dbg!("left");
-            },
-            BoardShift::Right => {
-                position.x = board_size - 1 - index;
-                //dbg!("right");
-            },            
-            BoardShift::Up => {
-                position.y = board_size - 1 - index;
-                //dbg!("up");
-            },
-            BoardShift::Down => {
-                position.y = index;
-                //dbg!("down");
-            },
+
+    /// Pulls a hand-edited settings file back into range, falling back
+    /// to the default for anything that can't be fixed up.
+    fn validated(mut self) -> Self {
+        let default = GameSettings::default();
+        self.board_size =
+            self.board_size.clamp(MIN_BOARD_SIZE, MAX_BOARD_SIZE);
+        // the first tile is a 2, so anything less is won before it starts
+        if self.win_target < 4 || !self.win_target.is_power_of_two() {
+            self.win_target = default.win_target;
         }
-    }
-    fn get_row_position(
-        &self,
-        position: &Position,
-    ) -> u8 {
-        match self {
-            BoardShift::Left => position.y,
-            BoardShift::Right => position.y,            
-            BoardShift::Up => position.x,
-            BoardShift::Down => position.x,
+        // a zero, negative or NaN speed has no duration to scale to
+        if !(self.animation_speed.is_finite() && self.animation_speed > 0.0)
+        {
+            self.animation_speed = default.animation_speed;
         }
+        self.move_queue_depth = self.move_queue_depth.max(1);
+        self
     }
 }
 
-struct NewTileEvent;
+fn load_settings() -> GameSettings {
+    match persist::load::<GameSettings>(SETTINGS_FILE) {
+        Ok(Some(settings)) => settings.validated(),
+        Ok(None) => GameSettings::default(),
+        Err(error) => {
+            warn!("could not load settings: {}", error);
+            GameSettings::default()
+        }
+    }
+}
 
 #[derive(Default)]
 struct Game {
     score: u32,
     score_best: u32,
+    score_best_by_size: BTreeMap<u8, u32>,
+    moves: u32,
+    won: bool,
+    hints_used: u32,
+    merges: u32,
+    seconds: f32,
+    /// Set once the game has been added to the lifetime stats.
+    stats_recorded: bool,
+}
+
+const BEST_SCORES_FILE: &str = "best_scores.ron";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct BestScores {
+    best: u32,
+    by_board_size: BTreeMap<u8, u32>,
+}
+
+impl Game {
+    fn best_scores(&self) -> BestScores {
+        BestScores {
+            best: self.score_best,
+            by_board_size: self.score_best_by_size.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum RunState {
+    MainMenu,
     Playing,
+    Won,
     GameOver,
+    Paused,
+    Settings,
+    Replay,
+    Stats,
 }
 
 fn main() {
+    if sim::headless_requested() {
+        if let Err(error) = sim::run() {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     App::new()
+        // picks up edited theme files
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..Default::default()
+        })
         .add_plugins(DefaultPlugins)
+        .add_plugin(ThemePlugin)
         .add_plugin(GameUiPlugin)
         .add_plugin(EasingsPlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(MoveInputPlugin)
+        .add_plugin(MenusPlugin)
+        .add_plugin(AutoPlayPlugin)
+        .add_plugin(HintPlugin)
+        .add_plugin(ReplayPlugin)
+        .add_plugin(StatsPlugin)
         .init_resource::<FontSpec>()
         .init_resource::<Game>()
+        .insert_resource(load_settings())
+        .init_resource::<TileDistribution>()
+        .insert_resource(GameRng::new(seed_or_random()))
         .add_event::<NewTileEvent>()
-        .add_state(RunState::Playing)
+        .add_event::<GameOverEvent>()
+        .add_state(RunState::MainMenu)
         .add_startup_system(setup)
         .add_startup_system(spawn_board)
+        .add_startup_system(load_best_scores)
         .add_system_set(
             SystemSet::on_update(RunState::Playing)
-            .with_system(render_tile_points)
             .with_system(board_shift)
-            .with_system(render_tiles)
-            .with_system(new_tile_handler)
-            .with_system(end_game),
+            .with_system(check_win)
+            .with_system(end_game.after(check_win)),
         )
+        .add_system_set(
+            SystemSet::on_resume(RunState::Playing)
+                .with_system(check_stuck),
+        )
+        // these only react to changes, so they keep running while
+        // `Won` sits on top of `Playing`
+        .add_system(render_tile_points)
+        .add_system(render_tiles)
+        .add_system(despawn_consumed)
+        // reading the tiles before this frame's shift means any merged
+        // tile is already gone, so the free cells match the rules
+        .add_system(new_tile_handler.before(board_shift))
+        .add_system_set(
+            SystemSet::on_update(RunState::GameOver)
+                .with_system(apply_board_size),
+        )
+        .add_system_set(
+            SystemSet::on_update(RunState::MainMenu)
+                .with_system(apply_board_size),
+        )
+        .add_system_to_stage(CoreStage::PostUpdate, apply_theme)
+        .add_system(fit_board_to_window)
+        .add_system(save_best_scores)
+        // a restart picks up a size changed during the game
         .add_system_set(
             SystemSet::on_enter(RunState::Playing)
+                .with_system(apply_board_size.before(game_reset))
                 .with_system(game_reset)
-                .with_system(spawn_tiles),
+                .with_system(spawn_tiles.after(game_reset)),
         )
         .run()
     
 }
 
+/// Runs once logging is set up, so a bad seed shows up in the log.
+fn seed_or_random() -> Option<u64> {
+    seed_from_args().unwrap_or_else(|error| {
+        warn!("{}, playing a random game instead", error);
+        None
+    })
+}
+
 fn setup(mut commands: Commands) {
     commands
         .spawn_bundle(Camera2dBundle::default());
 }
 
-fn spawn_board (mut commands: Commands) {
-    let board = Board::new(4);
+fn spawn_board(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    windows: Res<Windows>,
+) {
+    let board =
+        Board::fit_to_window(settings.board_size, &windows);
+    spawn_board_entity(&mut commands, board);
+}
 
+/// Colored by `apply_theme` once it's spawned.
+fn spawn_board_entity(commands: &mut Commands, board: Board) {
     commands
         .spawn_bundle(SpriteBundle {
             sprite: Sprite {
-                color: MATERIALS.board,
                 custom_size: Some(Vec2::new(
                     board.physical_size,
                     board.physical_size,
@@ -228,48 +412,174 @@ fn spawn_board (mut commands: Commands) {
             },
             ..Default::default()
         })
-        .with_children(|builder| {
-            for tile in (0..board.size)
-                .cartesian_product(0..board.size) //creates tuples of board coordinates to position tiles (0,0)..(3,3)
-            {
-                builder.spawn_bundle(SpriteBundle {
-                    sprite: Sprite {
-                        color: MATERIALS.tile_placeholder,
-                        custom_size: Some(Vec2::new(
-                            TILE_SIZE,
-                            TILE_SIZE,
-                        )),
-                        ..Sprite::default()
-                    },
-                    transform: Transform::from_xyz(
-                        board.cell_position_to_physical(
-                            tile.0,
-                        ),
-                        board.cell_position_to_physical(
-                            tile.1,
-                        ),
-                        1.0,
-                    ),
-                    ..Default::default()
-                });
-            }
-        })
+        .with_children(|builder| spawn_placeholders(builder, &board))
         .insert(board);
 }
 
+fn spawn_placeholders(builder: &mut ChildBuilder, board: &Board) {
+    for tile in (0..board.size)
+        .cartesian_product(0..board.size) //creates tuples of board coordinates to position tiles (0,0)..(3,3)
+    {
+        builder
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(board.tile_extent()),
+                    ..Sprite::default()
+                },
+                transform: Transform::from_xyz(
+                    board.cell_position_to_physical(tile.0),
+                    board.cell_position_to_physical(tile.1),
+                    1.0,
+                ),
+                ..Default::default()
+            })
+            .insert(TilePlaceholder {
+                x: tile.0,
+                y: tile.1,
+            });
+    }
+}
+
+/// Resizes the board to the size picked in the UI, or to the saved
+/// game's while it can still be continued. Only runs between games,
+/// so the old tiles are cleared along with it. The `Board` is changed
+/// in place, so a game starting this frame already lays its tiles out
+/// on the new size.
+fn apply_board_size(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    pending_restore: Res<PendingRestore>,
+    windows: Res<Windows>,
+    mut boards: Query<(Entity, &mut Board, &mut Sprite)>,
+    placeholders: Query<Entity, With<TilePlaceholder>>,
+    tiles: Query<Entity, With<Position>>,
+    mut history: ResMut<UndoHistory>,
+) {
+    let size = pending_restore.board_size().unwrap_or(settings.board_size);
+    let (board_entity, mut board, mut board_sprite) = boards.single_mut();
+    if board.size == size {
+        return;
+    }
+    // the old board's moves can't be taken back onto the new one
+    *history = UndoHistory::default();
+
+    for entity in placeholders.iter().chain(tiles.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+    *board = Board::fit_to_window(size, &windows);
+    board_sprite.custom_size =
+        Some(Vec2::new(board.physical_size, board.physical_size));
+    commands
+        .entity(board_entity)
+        .with_children(|builder| spawn_placeholders(builder, &board));
+}
+
+/// Recolors the board whenever the theme changes or its cells are
+/// spawned. Runs after `Update`, so a new board is never drawn
+/// uncolored.
+fn apply_theme(
+    theme: Res<Theme>,
+    mut clear_color: ResMut<ClearColor>,
+    new_placeholders: Query<(), Added<TilePlaceholder>>,
+    mut boards: Query<&mut Sprite, With<Board>>,
+    mut placeholders: Query<&mut Sprite, (With<TilePlaceholder>, Without<Board>)>,
+) {
+    if !theme.is_changed() && new_placeholders.is_empty() {
+        return;
+    }
+    clear_color.0 = theme.background;
+    for mut sprite in boards.iter_mut() {
+        sprite.color = theme.board;
+    }
+    for mut sprite in placeholders.iter_mut() {
+        sprite.color = theme.tile_placeholder;
+    }
+}
+
+fn fit_board_to_window(
+    mut commands: Commands,
+    mut resize_reader: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    mut boards: Query<(&mut Board, &mut Sprite)>,
+    mut placeholders: Query<
+        (&TilePlaceholder, &mut Transform, &mut Sprite),
+        Without<Board>,
+    >,
+    mut tiles: Query<
+        (Entity, &Position, &mut Transform, &mut Sprite),
+        (Without<Board>, Without<TilePlaceholder>),
+    >,
+) {
+    if resize_reader.iter().last().is_none() {
+        return;
+    }
+    let (mut board, mut board_sprite) = boards.single_mut();
+    *board = Board::fit_to_window(board.size, &windows);
+    board_sprite.custom_size =
+        Some(Vec2::new(board.physical_size, board.physical_size));
+
+    for (placeholder, mut transform, mut sprite) in
+        placeholders.iter_mut()
+    {
+        transform.translation.x =
+            board.cell_position_to_physical(placeholder.x);
+        transform.translation.y =
+            board.cell_position_to_physical(placeholder.y);
+        sprite.custom_size = Some(board.tile_extent());
+    }
+
+    for (entity, pos, mut transform, mut sprite) in tiles.iter_mut()
+    {
+        // a running ease would pull the tile back to its old spot
+        commands
+            .entity(entity)
+            .remove::<EasingComponent<Transform>>()
+            .remove::<EasingChainComponent<Transform>>();
+        transform.translation.x =
+            board.cell_position_to_physical(pos.x);
+        transform.translation.y =
+            board.cell_position_to_physical(pos.y);
+        transform.scale = Vec3::ONE;
+        sprite.custom_size = Some(board.tile_extent());
+    }
+}
+
 fn spawn_tiles(
     mut commands: Commands,
     query_board: Query<&Board>,
     font_spec: Res<FontSpec>,
+    distribution: Res<TileDistribution>,
+    mut rng: ResMut<GameRng>,
+    mut game: ResMut<Game>,
+    mut pending_restore: ResMut<PendingRestore>,
+    mut recorder: ResMut<ReplayRecorder>,
 ) {
     let board = query_board.single();
-    let mut rng = rand::thread_rng();
-    let starting_tiles: Vec<(u8, u8)> = (0..board.size)
-        .cartesian_product(0..board.size)
-        .choose_multiple(&mut rng, 2);
-    for (x, y) in starting_tiles.iter() {
-        let pos = Position { x: *x, y: *y};
-        spawn_tile(&mut commands, board, &font_spec, pos);
+    // `apply_board_size` has already put the board at the saved size
+    if let Some(saved) = pending_restore.take() {
+        saved.restore(
+            &mut commands,
+            board,
+            &font_spec,
+            &mut game,
+            &mut rng,
+            &mut recorder,
+        );
+        return;
+    }
+
+    rng.start_game();
+    info!("starting game with seed {}", rng.seed());
+    recorder.start(Replay::new(rng.seed(), board.size, &distribution));
+
+    let starting_tiles = GameState::new(board.size).deal(
+        STARTING_TILES,
+        &distribution,
+        &mut *rng,
+    );
+    for ((x, y), value) in starting_tiles {
+        let pos = Position { x, y };
+        spawn_tile(&mut commands, board, &font_spec, pos, value);
     }
 }
         
@@ -278,32 +588,32 @@ fn spawn_tile(
     board: &Board,
     font_spec: &Res<FontSpec>,
     pos: Position,
+    value: u32,
 ) {
     commands
         .spawn_bundle(SpriteBundle {
+            // colored by render_tile_points, which follows the theme
             sprite: Sprite {
-                color: MATERIALS.tile,
-                custom_size: Some(Vec2::new(
-                    TILE_SIZE, TILE_SIZE,
-                )),
+                custom_size: Some(board.tile_extent()),
                 ..Sprite::default()
             },
+            // starts hidden; render_tiles grows it in
             transform: Transform::from_xyz(
                 board.cell_position_to_physical(pos.x),
                 board.cell_position_to_physical(pos.y),
                 2.0,
-            ),
+            )
+            .with_scale(Vec3::ZERO),
             ..Default::default()
         })
         .with_children(|child_builder| {
             child_builder
                 .spawn_bundle(Text2dBundle {
                     text: Text::from_section(
-                        "2", 
+                        value.to_string(),
                         TextStyle { 
                             font: font_spec.family.clone(), 
-                            font_size: 40.0, 
-                            color: Color::BLACK, 
+                            font_size: board.tile_font_size(value),
                             ..Default::default()
                         },
                     )
@@ -318,21 +628,45 @@ fn spawn_tile(
                 })
                 .insert(TileText);
         })
-        .insert(Points { value : 2 })
+        .insert(Points { value })
         .insert(pos);
 }
 
 fn render_tile_points(
     mut texts: Query<&mut Text, With<TileText>>,
-    tiles: Query<(&Points, &Children)>,
+    mut tiles: Query<(
+        &Points,
+        ChangeTrackers<Points>,
+        &Children,
+        &mut Sprite,
+    )>,
+    query_board: Query<(&Board, ChangeTrackers<Board>)>,
+    theme: Res<Theme>,
 ){
-    for (points, children) in tiles.iter() {
+    let (board, board_tracker) = query_board.single();
+
+    for (points, points_tracker, children, mut sprite) in
+        tiles.iter_mut()
+    {
+        // a resized board changes the font size of every tile
+        if !points_tracker.is_changed()
+            && !board_tracker.is_changed()
+            && !theme.is_changed()
+        {
+            continue;
+        }
+        let color = theme.tile_color(points.value);
+        sprite.color = color;
+
         if let Some(entity) = children.first() {
             let mut text = texts
                 .get_mut(*entity)
                 .expect("expected Text to exist");
             let mut text_section = text.sections.first_mut().expect("expect first section to be accessible as mutable");
-            text_section.value = points.value.to_string()
+            text_section.value = points.value.to_string();
+            text_section.style.font_size =
+                board.tile_font_size(points.value);
+            text_section.style.color = theme.tile_text_color(color);
         }
     }
 
@@ -340,96 +674,183 @@ fn render_tile_points(
 
 fn board_shift (
     mut commands: Commands,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut tiles: Query<(Entity, &mut Position, &mut Points)>,
+    time: Res<Time>,
+    mut move_queue: ResMut<MoveQueue>,
+    animating: Query<
+        (),
+        Or<(
+            With<EasingComponent<Transform>>,
+            With<EasingChainComponent<Transform>>,
+        )>,
+    >,
+    mut tiles: Query<(Entity, &mut Position, &mut Points, &Transform)>,
     query_board: Query<&Board>,
     mut tile_writer: EventWriter<NewTileEvent>,
     mut game: ResMut<Game>,
+    mut history: ResMut<UndoHistory>,
+    mut recorder: ResMut<ReplayRecorder>,
+    rng: Res<GameRng>,
+    settings: Res<GameSettings>,
 ) {
     let board = query_board.single();
     let shift_direction =
-        keyboard_input.get_just_pressed().find_map(
-            |key_code| BoardShift::try_from(key_code).ok(),
-        );
+        move_queue.next_move(time.delta(), !animating.is_empty());
 
     if let Some(board_shift) = shift_direction {
-        let mut it =
-            tiles.iter_mut().sorted_by(|a, b| board_shift.sort(&a.1, &b.1))
-            .peekable();
+        let entities: HashMap<(u8, u8), Entity> = tiles
+            .iter()
+            .map(|(entity, pos, _, _)| ((pos.x, pos.y), entity))
+            .collect();
+        let mut state = GameState::from_tiles(
+            board.size,
+            tiles
+                .iter()
+                .map(|(_, pos, points, _)| ((pos.x, pos.y), points.value)),
+        );
 
-        let mut column: u8 = 0;
-        
-        while let Some(mut tile) = it.next() {
-            board_shift.set_column_position(
-                board.size,
-                &mut tile.1,
-                column,
+        let before = state.clone();
+        let outcome = state.shift(board_shift);
+        if !outcome.changed {
+            return;
+        }
+        history.record(Snapshot::capture(before, &game, &rng));
+        recorder.record(board_shift);
+        let slide = settings.animation(SLIDE_DURATION_MS);
+        move_queue.start_cooldown(slide);
+
+        for merge in outcome.merges.iter() {
+            let consumed = entities[&merge.consumed];
+            let (_, _, _, transform) = tiles
+                .get(consumed)
+                .expect("a consumed tile should be a live tile");
+            let target = Transform::from_xyz(
+                board.cell_position_to_physical(merge.to.0),
+                board.cell_position_to_physical(merge.to.1),
+                // just below the survivor so it slides underneath
+                transform.translation.z - 0.5,
             );
-            if let Some(tile_next) = it.peek() {
-                    if board_shift.get_row_position(&tile.1) != board_shift.get_row_position(&tile_next.1) {
-                        //different row, no merge
-                        column = 0;
-                    } else if tile.2.value != tile_next.2.value {
-                        //different value, no merge
-                        column = column + 1;
-                    } else{
-                        //merge
-                    let real_next_tile = it 
-                        .next()
-                        .expect("a peeked tile should always exist when we .next");
-                    tile.2.value = tile.2.value + real_next_tile.2.value;
-                    game.score += tile.2.value;
-
-                    if game.score_best < game.score {
-                        game.score_best = game.score;
-                    }
-
-                    commands
-                        .entity(real_next_tile.0)
-                        .despawn_recursive();
-
-                    if let Some(future) = it.peek() {
-                        if board_shift.get_row_position(&tile.1) != board_shift.get_row_position(&future.1) {
-                            column = 0;
-                        } else {
-                            column = column + 1;
-                        }
-                    }
-                }
-            }
+            commands
+                .entity(consumed)
+                .remove::<Position>()
+                .remove::<Points>()
+                .remove::<EasingChainComponent<Transform>>()
+                .insert(Consumed)
+                .insert(transform.ease_to(
+                    target,
+                    EaseFunction::QuadraticInOut,
+                    EasingType::Once { duration: slide },
+                ));
+            let (_, _, mut points, _) = tiles
+                .get_mut(entities[&merge.survivor])
+                .expect("a merge survivor should be a live tile");
+            points.value = merge.value;
+        }
+
+        for tile_move in outcome.moves.iter() {
+            let (_, mut pos, _, _) = tiles
+                .get_mut(entities[&tile_move.from])
+                .expect("a moved tile should be a live tile");
+            pos.x = tile_move.to.0;
+            pos.y = tile_move.to.1;
+        }
+
+        game.moves += 1;
+        game.merges += outcome.merges.len() as u32;
+        game.score += outcome.score_delta;
+        if game.score_best < game.score {
+            game.score_best = game.score;
         }
-        //dbg!(game.score);
+        let score = game.score;
+        let size_best =
+            game.score_best_by_size.entry(board.size).or_insert(0);
+        if *size_best < score {
+            *size_best = score;
+        }
+
         tile_writer.send(NewTileEvent);
     }
 }
 
+/// Slides moved tiles to their cell, pops tiles that grew from a
+/// merge once they arrive, and grows new tiles in after the slide.
 fn render_tiles(
     mut commands: Commands,
-    mut tiles: Query<
-        (Entity, &mut Transform, &Position),
-        Changed<Position>,
+    tiles: Query<
+        (
+            Entity,
+            &Transform,
+            &Position,
+            ChangeTrackers<Points>,
+        ),
+        Or<(Changed<Position>, Changed<Points>)>,
     >,
     query_board: Query<&Board>,
+    settings: Res<GameSettings>,
 ) {
     let board = query_board.single();
+    let slide = EasingType::Once {
+        duration: settings.animation(SLIDE_DURATION_MS),
+    };
 
-     for (entity, mut transform, pos) in tiles.iter_mut() {
-            let x = board.cell_position_to_physical(pos.x);
-            let y = board.cell_position_to_physical(pos.y);
-
-            commands.entity(entity).insert(transform.ease_to(
-                Transform::from_xyz(
-                    x,
-                    y,
-                    transform.translation.z,
-                ),
-                EaseFunction::QuadraticInOut,
-                EasingType::Once {
-                    duration: std::time::Duration::from_millis(
-                        100,
+    for (entity, transform, pos, points_tracker) in tiles.iter() {
+        let target = Transform::from_xyz(
+            board.cell_position_to_physical(pos.x),
+            board.cell_position_to_physical(pos.y),
+            transform.translation.z,
+        );
+        let mut tile = commands.entity(entity);
+        // a tile carries at most one running ease
+        tile.remove::<EasingComponent<Transform>>()
+            .remove::<EasingChainComponent<Transform>>();
+
+        if points_tracker.is_added() {
+            let hidden = target.with_scale(Vec3::ZERO);
+            tile.insert(
+                hidden
+                    .ease_to(hidden, EaseFunction::QuadraticInOut, slide)
+                    .ease_to(
+                        target,
+                        EaseFunction::QuadraticOut,
+                        EasingType::Once {
+                            duration: settings.animation(GROW_DURATION_MS),
+                        },
                     ),
-                },
+            );
+        } else if points_tracker.is_changed() {
+            let pop = EasingType::Once {
+                duration: settings.animation(POP_DURATION_MS),
+            };
+            tile.insert(
+                transform
+                    .ease_to(target, EaseFunction::QuadraticInOut, slide)
+                    .ease_to(
+                        target.with_scale(Vec3::splat(POP_SCALE)),
+                        EaseFunction::QuadraticOut,
+                        pop,
+                    )
+                    .ease_to(target, EaseFunction::QuadraticIn, pop),
+            );
+        } else {
+            tile.insert(transform.ease_to(
+                target,
+                EaseFunction::QuadraticInOut,
+                slide,
             ));
+        }
+    }
+}
+
+/// bevy_easings drops the ease once it finishes, which is our cue
+/// that a consumed tile has reached the survivor.
+fn despawn_consumed(
+    mut commands: Commands,
+    consumed: Query<
+        Entity,
+        (With<Consumed>, Without<EasingComponent<Transform>>),
+    >,
+) {
+    for entity in consumed.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 }
 
@@ -437,96 +858,150 @@ fn new_tile_handler(
     mut tile_reader: EventReader<NewTileEvent>,
     mut commands: Commands,
     query_board: Query<&Board>,
-    tiles: Query<&Position>,
-    font_spec: Res<FontSpec>
+    tiles: Query<(&Position, &Points)>,
+    font_spec: Res<FontSpec>,
+    distribution: Res<TileDistribution>,
+    mut rng: ResMut<GameRng>,
+    game: Res<Game>,
+    run_state: Res<State<RunState>>,
+    mut game_over_writer: EventWriter<GameOverEvent>,
 ) {
+    if tile_reader.is_empty() {
+        return;
+    }
     let board = query_board.single();
+    let mut state = GameState::from_tiles(
+        board.size,
+        tiles.iter().map(|(pos, points)| ((pos.x, pos.y), points.value)),
+    );
 
     for _event in tile_reader.iter() {
         //insert new tile
-        let mut rng = rand::thread_rng();
-        let possible_position: Option<Position> = (
-            0..board.size)
-            .cartesian_product(0..board.size)
-            .filter_map(|tile_pos| {
-                let new_pos = Position {
-                    x: tile_pos.0,
-                    y: tile_pos.1,
-                };
-                match tiles
-                    .iter()
-                    .find(|&&pos| pos == new_pos)
-                {
-                    Some(_) => None,
-                    None => Some(new_pos),
-                }
-            })
-            .choose(&mut rng);
-
-        if let Some(pos) = possible_position {
+        if let Some(((x, y), value)) =
+            state.spawn_random(&distribution, &mut *rng)
+        {
             spawn_tile(
                 &mut commands,
                 board,
                 &font_spec,
-                pos,
+                Position { x, y },
+                value,
             );
         }
     }
+
+    // a win sitting on top gets the same check once it's dismissed
+    if *run_state.current() == RunState::Playing {
+        if let Some(event) = GameOverEvent::check(&state, &game) {
+            game_over_writer.send(event);
+        }
+    }
 }
 
-fn end_game(
-    tiles: Query<(&Position, &Points)>,
+/// Looks at every tile rather than the changed ones, so a win that
+/// lost out to a state change already queued this frame (a pause, a
+/// restart) is picked up again next time round.
+fn check_win(
+    tiles: Query<&Points>,
+    settings: Res<GameSettings>,
+    mut game: ResMut<Game>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if game.won {
+        return;
+    }
+    if tiles
+        .iter()
+        .any(|points| points.value >= settings.win_target)
+        && run_state.push(RunState::Won).is_ok()
+    {
+        game.won = true;
+    }
+}
+
+/// Catches a board that filled up while `Won` was on top of it.
+fn check_stuck(
     query_board: Query<&Board>,
+    tiles: Query<(&Position, &Points)>,
+    game: Res<Game>,
+    mut game_over_writer: EventWriter<GameOverEvent>,
+) {
+    let state = GameState::from_tiles(
+        query_board.single().size,
+        tiles.iter().map(|(pos, points)| ((pos.x, pos.y), points.value)),
+    );
+    if let Some(event) = GameOverEvent::check(&state, &game) {
+        game_over_writer.send(event);
+    }
+}
+
+fn end_game(
+    mut game_over_reader: EventReader<GameOverEvent>,
     mut run_state: ResMut<State<RunState>>,
 ) {
-    let board = query_board.single();
+    if let Some(event) = game_over_reader.iter().last() {
+        // `Playing` stays underneath so the last move can be undone.
+        // A win queued by the same move goes first; `check_stuck` ends
+        // the game once it's dismissed.
+        if run_state.push(RunState::GameOver).is_err() {
+            return;
+        }
+        info!(
+            "game over with score {}, max tile {} after {} moves",
+            event.score, event.max_tile, event.moves
+        );
+    }
+}
 
-    if tiles.iter().len() == 16 {
-        let map: HashMap<&Position, &Points> =
-        tiles.iter().collect();
-
-        let neighbor_points =
-        [(-1, 0), (0, 1), (1, 0), (0, -1),];
-        let board_range: Range<i8> = 0..(board.size as i8);
-
-        let has_move = tiles
-            .iter().any(
-                |(Position {x, y}, value)| {
-                neighbor_points
-                    .iter()
-                    .filter_map(|(x2, y2)| {
-                        let new_x = *x as i8 - x2;
-                        let new_y = *y as i8 -y2;
-
-                        if !board_range.contains(&new_x)
-                            || !board_range.contains(&new_y) 
-                        {
-                            return None;
-                        };
-
-                        map.get(&Position {
-                            x: new_x.try_into().unwrap(),
-                            y: new_y.try_into().unwrap(),
-                        })
-                    })
-                    .any(|&v| v == value)
-                },
-            );
+fn load_best_scores(mut game: ResMut<Game>) {
+    match persist::load::<BestScores>(BEST_SCORES_FILE) {
+        Ok(Some(best_scores)) => {
+            game.score_best = best_scores.best;
+            game.score_best_by_size = best_scores.by_board_size;
+        }
+        Ok(None) => {}
+        Err(error) => warn!("could not load best scores: {}", error),
+    }
+}
 
-            if has_move == false {
-                dbg!("game over");
-                run_state.set(RunState::GameOver).unwrap();
-            }
-    };
+fn save_best_scores(
+    game: Res<Game>,
+    mut saved: Local<BestScores>,
+) {
+    if !game.is_changed() {
+        return;
+    }
+    let best_scores = game.best_scores();
+    if best_scores == *saved {
+        return;
+    }
+    if let Err(error) = persist::save(BEST_SCORES_FILE, &best_scores) {
+        warn!("could not save best scores: {}", error);
+    }
+    *saved = best_scores;
 }
 
 fn game_reset(
     mut commands: Commands,
     tiles: Query<Entity, With<Position>>,
     mut game: ResMut<Game>,
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    mut new_tile_events: ResMut<Events<NewTileEvent>>,
 ) {
     for entity in tiles.iter() {
         commands.entity(entity).despawn_recursive();
     }
+    // leaving `GameOver` uncovers the stuck board for `check_stuck`,
+    // which must not end the new game
+    game_over_events.clear();
+    // a move made in the same frame as a restart must not spawn its
+    // tile into the new game
+    new_tile_events.clear();
     game.score = 0;
+    game.moves = 0;
+    game.won = false;
+    game.hints_used = 0;
+    game.merges = 0;
+    game.seconds = 0.0;
+    game.stats_recorded = false;
 }
\ No newline at end of file
@@ -0,0 +1,577 @@
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    colors::{Theme, ThemeColor, Themes},
+    input::{Action, BindingPreset, KeyBindings, KeyChord},
+    persist,
+    save::PendingRestore,
+    ui::spawn_button,
+    FontSpec, GameSettings, RunState, ANIMATION_SPEEDS, MAX_BOARD_SIZE,
+    MIN_BOARD_SIZE, SETTINGS_FILE,
+};
+
+const MODIFIER_KEYS: [KeyCode; 8] = [
+    KeyCode::LControl,
+    KeyCode::RControl,
+    KeyCode::LShift,
+    KeyCode::RShift,
+    KeyCode::LAlt,
+    KeyCode::RAlt,
+    KeyCode::LWin,
+    KeyCode::RWin,
+];
+
+#[derive(Component)]
+pub struct MainMenu;
+
+#[derive(Component)]
+pub struct SettingsMenu;
+
+#[derive(Component)]
+pub struct PauseMenu;
+
+#[derive(Component)]
+pub enum MainMenuButton {
+    Continue,
+    NewGame,
+    Settings,
+    Stats,
+    Quit,
+}
+
+#[derive(Component)]
+pub enum PauseMenuButton {
+    Resume,
+    Settings,
+    Restart,
+    Quit,
+}
+
+#[derive(Component)]
+pub struct BindingText(Action);
+
+#[derive(Component)]
+pub struct RebindPrompt;
+
+#[derive(Component)]
+pub enum SettingsMenuButton {
+    Rebind(Action),
+    ClearBinding(Action),
+    Preset(BindingPreset),
+    BoardSize(i8),
+    Theme,
+    AnimationSpeed,
+    HintScores,
+    Back,
+}
+
+/// Shows the size the next game will be played at.
+#[derive(Component)]
+pub struct SettingsBoardSizeText;
+
+/// The action waiting for its next key press, if any.
+#[derive(Default)]
+pub struct RebindTarget(Option<Action>);
+
+pub struct MenusPlugin;
+
+impl Plugin for MenusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RebindTarget>()
+            .add_system_set(
+                SystemSet::on_enter(RunState::MainMenu)
+                    .with_system(spawn_main_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::MainMenu)
+                    .with_system(main_menu_buttons),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::MainMenu)
+                    .with_system(despawn_with::<MainMenu>),
+            )
+            // a replay or a screen pushed on top needs the window to
+            // itself, like the game-over overlay does
+            .add_system_set(
+                SystemSet::on_pause(RunState::MainMenu)
+                    .with_system(despawn_with::<MainMenu>),
+            )
+            .add_system_set(
+                SystemSet::on_resume(RunState::MainMenu)
+                    .with_system(spawn_main_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(RunState::Settings)
+                    .with_system(spawn_settings_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Settings)
+                    .with_system(settings_buttons)
+                    .with_system(capture_rebind)
+                    .with_system(binding_texts)
+                    .with_system(settings_texts),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Settings)
+                    .with_system(despawn_with::<SettingsMenu>)
+                    .with_system(save_settings),
+            )
+            .add_system_set(
+                SystemSet::on_enter(RunState::Paused)
+                    .with_system(spawn_pause_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Paused)
+                    .with_system(pause_menu_buttons),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Paused)
+                    .with_system(despawn_with::<PauseMenu>),
+            );
+    }
+}
+
+pub fn overlay() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+pub fn row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        color: UiColor(Color::NONE),
+        ..Default::default()
+    }
+}
+
+pub fn label(
+    font_spec: &FontSpec,
+    value: &str,
+    font_size: f32,
+) -> TextBundle {
+    TextBundle {
+        text: Text::from_section(
+            value,
+            TextStyle {
+                font: font_spec.family.clone(),
+                font_size,
+                ..Default::default()
+            },
+        ),
+        style: Style {
+            margin: UiRect::all(Val::Px(6.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn column() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::FlexEnd,
+            margin: UiRect::all(Val::Px(20.0)),
+            ..Default::default()
+        },
+        color: UiColor(Color::NONE),
+        ..Default::default()
+    }
+}
+
+fn spawn_main_menu(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+    pending_restore: Res<PendingRestore>,
+) {
+    commands
+        .spawn_bundle(overlay())
+        .insert(ThemeColor::Overlay)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(label(&font_spec, "2048", 80.0))
+                .insert(ThemeColor::Text);
+            if pending_restore.has_game() {
+                spawn_button(parent, &font_spec, "Continue", 160.0)
+                    .insert(MainMenuButton::Continue);
+            }
+            for (button, text) in [
+                (MainMenuButton::NewGame, "New game"),
+                (MainMenuButton::Settings, "Settings"),
+                (MainMenuButton::Stats, "Stats"),
+                (MainMenuButton::Quit, "Quit"),
+            ] {
+                spawn_button(parent, &font_spec, text, 160.0).insert(button);
+            }
+        })
+        .insert(MainMenu);
+}
+
+fn main_menu_buttons(
+    interaction_query: Query<
+        (&Interaction, &MainMenuButton),
+        Changed<Interaction>,
+    >,
+    mut pending_restore: ResMut<PendingRestore>,
+    mut run_state: ResMut<State<RunState>>,
+    mut exit_writer: EventWriter<AppExit>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            MainMenuButton::Continue => {
+                let _ = run_state.set(RunState::Playing);
+            }
+            MainMenuButton::NewGame => {
+                pending_restore.take();
+                let _ = run_state.set(RunState::Playing);
+            }
+            MainMenuButton::Settings => {
+                let _ = run_state.push(RunState::Settings);
+            }
+            MainMenuButton::Stats => {
+                let _ = run_state.push(RunState::Stats);
+            }
+            MainMenuButton::Quit => exit_writer.send(AppExit),
+        }
+        return;
+    }
+}
+
+fn spawn_settings_menu(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+) {
+    commands
+        .spawn_bundle(overlay())
+        .insert(ThemeColor::Overlay)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(label(&font_spec, "Settings", 40.0))
+                .insert(ThemeColor::Text);
+
+            parent.spawn_bundle(row()).with_children(|parent| {
+                parent.spawn_bundle(column()).with_children(|parent| {
+                    parent
+                        .spawn_bundle(label(&font_spec, "Game", 28.0))
+                        .insert(ThemeColor::Text);
+
+                    parent.spawn_bundle(row()).with_children(|parent| {
+                        parent
+                            .spawn_bundle(label(
+                                &font_spec,
+                                "Board size (next game)",
+                                20.0,
+                            ))
+                            .insert(ThemeColor::Text);
+                        spawn_button(parent, &font_spec, "-", 30.0)
+                            .insert(SettingsMenuButton::BoardSize(-1));
+                        parent
+                            .spawn_bundle(label(&font_spec, "", 20.0))
+                            .insert(ThemeColor::Text)
+                            .insert(SettingsBoardSizeText);
+                        spawn_button(parent, &font_spec, "+", 30.0)
+                            .insert(SettingsMenuButton::BoardSize(1));
+                    });
+
+                    for (button, text) in [
+                        (SettingsMenuButton::Theme, "Theme"),
+                        (SettingsMenuButton::AnimationSpeed, "Animation speed"),
+                        (SettingsMenuButton::HintScores, "Hint scores"),
+                    ] {
+                        parent.spawn_bundle(row()).with_children(|parent| {
+                            parent
+                                .spawn_bundle(label(&font_spec, text, 20.0))
+                                .insert(ThemeColor::Text);
+                            spawn_button(parent, &font_spec, "", 140.0)
+                                .insert(button);
+                        });
+                    }
+                });
+
+                parent.spawn_bundle(column()).with_children(|parent| {
+                    parent
+                        .spawn_bundle(label(&font_spec, "Controls", 28.0))
+                        .insert(ThemeColor::Text);
+
+                    for action in Action::ALL {
+                        parent.spawn_bundle(row()).with_children(|parent| {
+                            parent
+                                .spawn_bundle(label(
+                                    &font_spec,
+                                    action.label(),
+                                    20.0,
+                                ))
+                                .insert(ThemeColor::Text);
+                            parent
+                                .spawn_bundle(label(&font_spec, "", 20.0))
+                                .insert(ThemeColor::Text)
+                                .insert(BindingText(action));
+                            spawn_button(parent, &font_spec, "Add", 60.0)
+                                .insert(SettingsMenuButton::Rebind(action));
+                            spawn_button(parent, &font_spec, "Clear", 60.0)
+                                .insert(SettingsMenuButton::ClearBinding(
+                                    action,
+                                ));
+                        });
+                    }
+
+                    parent.spawn_bundle(row()).with_children(|parent| {
+                        parent
+                            .spawn_bundle(label(&font_spec, "Presets", 20.0))
+                            .insert(ThemeColor::Text);
+                        for preset in BindingPreset::ALL {
+                            spawn_button(
+                                parent,
+                                &font_spec,
+                                preset.label(),
+                                80.0,
+                            )
+                            .insert(SettingsMenuButton::Preset(preset));
+                        }
+                    });
+                });
+            });
+
+            parent
+                .spawn_bundle(label(&font_spec, "", 20.0))
+                .insert(ThemeColor::Text)
+                .insert(RebindPrompt);
+            spawn_button(parent, &font_spec, "Back", 100.0)
+                .insert(SettingsMenuButton::Back);
+        })
+        .insert(SettingsMenu);
+}
+
+fn settings_buttons(
+    interaction_query: Query<
+        (&Interaction, &SettingsMenuButton),
+        Changed<Interaction>,
+    >,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut rebind_target: ResMut<RebindTarget>,
+    mut settings: ResMut<GameSettings>,
+    themes: Res<Themes>,
+    theme_assets: Res<Assets<Theme>>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        // clicking anything else abandons a pending rebind
+        rebind_target.0 = None;
+        match button {
+            SettingsMenuButton::Rebind(action) => {
+                rebind_target.0 = Some(*action);
+            }
+            SettingsMenuButton::ClearBinding(action) => {
+                key_bindings.clear(*action);
+            }
+            SettingsMenuButton::Preset(preset) => {
+                *key_bindings = KeyBindings::preset(*preset);
+            }
+            SettingsMenuButton::BoardSize(step) => {
+                settings.board_size = (settings.board_size as i8 + step)
+                    .clamp(MIN_BOARD_SIZE as i8, MAX_BOARD_SIZE as i8)
+                    as u8;
+            }
+            SettingsMenuButton::Theme => {
+                let names = themes.names(&theme_assets);
+                if names.is_empty() {
+                    continue;
+                }
+                let current = names
+                    .iter()
+                    .position(|name| *name == settings.theme)
+                    .unwrap_or(0);
+                settings.theme = names[(current + 1) % names.len()].clone();
+            }
+            SettingsMenuButton::AnimationSpeed => {
+                let current = ANIMATION_SPEEDS
+                    .iter()
+                    .position(|speed| *speed == settings.animation_speed)
+                    .unwrap_or(0);
+                settings.animation_speed =
+                    ANIMATION_SPEEDS[(current + 1) % ANIMATION_SPEEDS.len()];
+            }
+            SettingsMenuButton::HintScores => {
+                settings.hint_scores = !settings.hint_scores;
+            }
+            SettingsMenuButton::Back => {
+                let _ = run_state.pop();
+            }
+        }
+    }
+}
+
+fn capture_rebind(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut rebind_target: ResMut<RebindTarget>,
+) {
+    let action = match rebind_target.0 {
+        Some(action) => action,
+        None => return,
+    };
+    let key = keyboard_input
+        .get_just_pressed()
+        .find(|key| !MODIFIER_KEYS.contains(key));
+
+    if let Some(key) = key {
+        let chord = KeyChord {
+            key: *key,
+            ctrl: keyboard_input.pressed(KeyCode::LControl)
+                || keyboard_input.pressed(KeyCode::RControl),
+            shift: keyboard_input.pressed(KeyCode::LShift)
+                || keyboard_input.pressed(KeyCode::RShift),
+        };
+        key_bindings.bind(chord, action);
+        rebind_target.0 = None;
+    }
+}
+
+fn binding_texts(
+    key_bindings: Res<KeyBindings>,
+    rebind_target: Res<RebindTarget>,
+    mut texts: ParamSet<(
+        Query<(&mut Text, &BindingText)>,
+        Query<&mut Text, With<RebindPrompt>>,
+    )>,
+) {
+    for (mut text, binding) in texts.p0().iter_mut() {
+        let chords: Vec<String> = key_bindings
+            .chords_for(binding.0)
+            .map(|chord| chord.to_string())
+            .collect();
+        text.sections[0].value = if chords.is_empty() {
+            "-".to_string()
+        } else {
+            chords.join(", ")
+        };
+    }
+
+    let prompt = match rebind_target.0 {
+        Some(action) => format!("Press a key for {}", action.label()),
+        None => String::new(),
+    };
+    for mut text in texts.p1().iter_mut() {
+        text.sections[0].value = prompt.clone();
+    }
+}
+
+fn settings_texts(
+    settings: Res<GameSettings>,
+    buttons: Query<(&Children, &SettingsMenuButton)>,
+    mut texts: ParamSet<(
+        Query<&mut Text>,
+        Query<&mut Text, With<SettingsBoardSizeText>>,
+    )>,
+) {
+    for (children, button) in buttons.iter() {
+        let value = match button {
+            SettingsMenuButton::Theme => settings.theme.clone(),
+            SettingsMenuButton::AnimationSpeed => {
+                format!("{}x", settings.animation_speed)
+            }
+            SettingsMenuButton::HintScores => {
+                let value = if settings.hint_scores { "On" } else { "Off" };
+                value.to_string()
+            }
+            _ => continue,
+        };
+        let mut text_query = texts.p0();
+        let mut text = text_query
+            .get_mut(children[0])
+            .expect("expect button to have a text child");
+        text.sections[0].value = value;
+    }
+    for mut text in texts.p1().iter_mut() {
+        text.sections[0].value = format!(
+            "{size}x{size}",
+            size = settings.board_size
+        );
+    }
+}
+
+fn save_settings(settings: Res<GameSettings>) {
+    if let Err(error) = persist::save(SETTINGS_FILE, &*settings) {
+        warn!("could not save settings: {}", error);
+    }
+}
+
+fn spawn_pause_menu(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+) {
+    commands
+        .spawn_bundle(overlay())
+        .insert(ThemeColor::Overlay)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(label(&font_spec, "Paused", 60.0))
+                .insert(ThemeColor::Text);
+            for (button, text) in [
+                (PauseMenuButton::Resume, "Resume"),
+                (PauseMenuButton::Settings, "Settings"),
+                (PauseMenuButton::Restart, "Restart"),
+                (PauseMenuButton::Quit, "Quit"),
+            ] {
+                spawn_button(parent, &font_spec, text, 160.0).insert(button);
+            }
+        })
+        .insert(PauseMenu);
+}
+
+fn pause_menu_buttons(
+    interaction_query: Query<
+        (&Interaction, &PauseMenuButton),
+        Changed<Interaction>,
+    >,
+    mut run_state: ResMut<State<RunState>>,
+    mut exit_writer: EventWriter<AppExit>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            PauseMenuButton::Resume => {
+                let _ = run_state.pop();
+            }
+            PauseMenuButton::Settings => {
+                let _ = run_state.push(RunState::Settings);
+            }
+            // leaves the pause behind along with the old game
+            PauseMenuButton::Restart => {
+                let _ = run_state.replace(RunState::Playing);
+            }
+            PauseMenuButton::Quit => exit_writer.send(AppExit),
+        }
+        return;
+    }
+}
+
+pub fn despawn_with<T: Component>(
+    mut commands: Commands,
+    query: Query<Entity, With<T>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
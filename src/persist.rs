@@ -0,0 +1,86 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+const APP_DIR: &str = "boxes";
+
+/// The per-user directory our files live in, following each
+/// platform's convention for application data.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| {
+            PathBuf::from(home).join("Library/Application Support")
+        })
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| {
+                    PathBuf::from(home).join(".local/share")
+                })
+            })
+    };
+    base.map(|dir| dir.join(APP_DIR))
+}
+
+/// Reads a RON file from the data dir. A missing file is `Ok(None)`.
+pub fn load<T: DeserializeOwned>(file_name: &str) -> io::Result<Option<T>> {
+    let path = match data_dir() {
+        Some(dir) => dir.join(file_name),
+        None => return Ok(None),
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(None)
+        }
+        Err(error) => return Err(error),
+    };
+    ron::from_str(&contents)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Writes a RON file to the data dir. The contents go to a temporary
+/// file first and are renamed into place, so a crash mid-write never
+/// leaves a truncated file behind.
+pub fn save<T: Serialize>(file_name: &str, value: &T) -> io::Result<()> {
+    let dir = data_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no data directory")
+    })?;
+    fs::create_dir_all(&dir)?;
+
+    let contents =
+        ron::ser::to_string_pretty(value, Default::default())
+            .map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidData, error)
+            })?;
+
+    let path = dir.join(file_name);
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, &path)
+}
+
+/// Deletes a file from the data dir, if it is there.
+pub fn remove(file_name: &str) -> io::Result<()> {
+    let path = match data_dir() {
+        Some(dir) => dir.join(file_name),
+        None => return Ok(()),
+    };
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
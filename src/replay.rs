@@ -0,0 +1,547 @@
+//! Recording games as their seed and inputs, and playing them back.
+//!
+//! Tile spawns come from `GameRng`, so a replay doesn't store the
+//! board at all: the seed, board size, spawn distribution and moves
+//! rebuild every position exactly.
+
+use std::{collections::HashSet, env, fs, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    colors::ThemeColor,
+    persist,
+    rng::GameRng,
+    rules::{BoardShift, GameState, TileDistribution},
+    spawn_board_entity, spawn_tile,
+    ui::spawn_button,
+    Board, FontSpec, GameOverEvent, Points, Position, RunState,
+    MAX_BOARD_SIZE, MIN_BOARD_SIZE, STARTING_TILES,
+};
+
+const LAST_REPLAY_FILE: &str = "last_replay.ron";
+/// Bump whenever `Replay` changes shape or the rules it replays do.
+const REPLAY_VERSION: u32 = 1;
+const REPLAY_FLAG: &str = "--replay";
+/// How long each move stays up at 1x speed.
+const STEP_SECONDS: f32 = 0.5;
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const DEFAULT_SPEED: usize = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    version: u32,
+    seed: u64,
+    board_size: u8,
+    distribution: TileDistribution,
+    moves: Vec<BoardShift>,
+}
+
+impl Replay {
+    pub fn new(
+        seed: u64,
+        board_size: u8,
+        distribution: &TileDistribution,
+    ) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            board_size,
+            distribution: distribution.clone(),
+            moves: vec![],
+        }
+    }
+
+    /// Whether the board and tile spawns can be rebuilt at all, with
+    /// the same limits a saved game is held to.
+    fn is_valid(&self) -> bool {
+        let weights = &self.distribution.weights;
+        (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&self.board_size)
+            && weights.iter().any(|(_, weight)| *weight > 0)
+            && weights
+                .iter()
+                .all(|(value, _)| *value > 1 && value.is_power_of_two())
+    }
+
+    /// Plays the game back: the board and score before the first move
+    /// and after each one.
+    fn frames(&self) -> Vec<(GameState, u32)> {
+        let mut rng = GameRng::new(Some(self.seed));
+        rng.start_game();
+        let mut state = GameState::new(self.board_size);
+        state.deal(STARTING_TILES, &self.distribution, &mut rng);
+
+        let mut score = 0;
+        let mut frames = vec![(state.clone(), score)];
+        for board_shift in self.moves.iter() {
+            let outcome = state.shift(*board_shift);
+            score += outcome.score_delta;
+            if outcome.changed {
+                state.spawn_random(&self.distribution, &mut rng);
+            }
+            frames.push((state.clone(), score));
+        }
+        frames
+    }
+}
+
+/// The replay of the game being played. Undone moves are kept aside
+/// so a redo can put them back.
+#[derive(Default)]
+pub struct ReplayRecorder {
+    replay: Option<Replay>,
+    undone: Vec<BoardShift>,
+}
+
+impl ReplayRecorder {
+    pub fn start(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+        self.undone.clear();
+    }
+
+    /// Picks up recording a resumed game, if its save had a replay.
+    pub fn resume(&mut self, replay: Option<Replay>) {
+        self.replay = replay;
+        self.undone.clear();
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    pub fn record(&mut self, board_shift: BoardShift) {
+        if let Some(replay) = &mut self.replay {
+            replay.moves.push(board_shift);
+            self.undone.clear();
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            self.undone.extend(replay.moves.pop());
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            replay.moves.extend(self.undone.pop());
+        }
+    }
+}
+
+/// A replay waiting for `RunState::Replay` to open it.
+#[derive(Default)]
+pub struct PendingReplay(pub Option<Replay>);
+
+/// The replay on screen, and the game it's covering up.
+pub struct ReplayViewer {
+    frames: Vec<(GameState, u32)>,
+    index: usize,
+    shown: Option<usize>,
+    playing: bool,
+    speed: usize,
+    timer: Timer,
+    live: GameState,
+}
+
+impl ReplayViewer {
+    /// The tiles of the game that was up when the viewer opened.
+    pub fn live(&self) -> &GameState {
+        &self.live
+    }
+
+    fn step_forward(&mut self) {
+        if self.index + 1 < self.frames.len() {
+            self.index += 1;
+        } else {
+            self.playing = false;
+        }
+    }
+
+    fn step_back(&mut self) {
+        self.index = self.index.saturating_sub(1);
+    }
+
+    fn set_speed(&mut self, speed: usize) {
+        self.speed = speed.min(SPEEDS.len() - 1);
+        self.timer.set_duration(Duration::from_secs_f32(
+            STEP_SECONDS / SPEEDS[self.speed],
+        ));
+    }
+}
+
+#[derive(Component)]
+pub struct ReplayControls;
+
+#[derive(Component)]
+pub struct ReplayStatusText;
+
+#[derive(Component, Clone, Copy)]
+pub enum ReplayControl {
+    Back,
+    PlayPause,
+    Forward,
+    Slower,
+    Faster,
+    Exit,
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayRecorder>()
+            .init_resource::<PendingReplay>()
+            .add_startup_system(load_replay_from_args)
+            .add_system(open_pending_replay)
+            .add_system(save_finished_replay)
+            .add_system_set(
+                SystemSet::on_enter(RunState::Replay)
+                    .with_system(enter_replay),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Replay)
+                    .with_system(replay_controls)
+                    .with_system(replay_keys)
+                    .with_system(
+                        replay_timer
+                            .after(replay_controls)
+                            .after(replay_keys),
+                    )
+                    .with_system(show_replay_frame.after(replay_timer)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Replay)
+                    .with_system(exit_replay),
+            );
+    }
+}
+
+/// `--replay <file>` opens a saved replay instead of the game.
+fn load_replay_from_args(mut pending: ResMut<PendingReplay>) {
+    let mut args = env::args().skip(1);
+    let path = match args.by_ref().find(|arg| arg == REPLAY_FLAG) {
+        Some(_) => match args.next() {
+            Some(path) => path,
+            None => {
+                warn!("{} needs a file name", REPLAY_FLAG);
+                return;
+            }
+        },
+        None => return,
+    };
+    let replay = fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| {
+            ron::from_str::<Replay>(&text).map_err(|error| error.to_string())
+        });
+    match replay {
+        Ok(replay) if replay.version != REPLAY_VERSION => warn!(
+            "can't play replay from version {}, expected {}",
+            replay.version, REPLAY_VERSION
+        ),
+        Ok(replay) if !replay.is_valid() => warn!(
+            "can't play replay {}: impossible board size or tile weights",
+            path
+        ),
+        Ok(replay) => pending.0 = Some(replay),
+        Err(error) => warn!("could not read replay {}: {}", path, error),
+    }
+}
+
+fn open_pending_replay(
+    pending: Res<PendingReplay>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if pending.0.is_some()
+        && matches!(
+            run_state.current(),
+            RunState::MainMenu | RunState::Playing | RunState::GameOver
+        )
+    {
+        let _ = run_state.push(RunState::Replay);
+    }
+}
+
+/// Keeps the last finished game around as `last_replay.ron`.
+fn save_finished_replay(
+    mut game_over_reader: EventReader<GameOverEvent>,
+    recorder: Res<ReplayRecorder>,
+) {
+    if game_over_reader.iter().last().is_none() {
+        return;
+    }
+    if let Some(replay) = recorder.replay() {
+        if let Err(error) = persist::save(LAST_REPLAY_FILE, replay) {
+            warn!("could not save the replay: {}", error);
+        }
+    }
+}
+
+fn enter_replay(
+    mut commands: Commands,
+    mut pending: ResMut<PendingReplay>,
+    mut run_state: ResMut<State<RunState>>,
+    windows: Res<Windows>,
+    font_spec: Res<FontSpec>,
+    boards: Query<(Entity, &Board)>,
+    tiles: Query<(Entity, &Position, &Points)>,
+) {
+    let replay = match pending.0.take() {
+        Some(replay) => replay,
+        None => {
+            let _ = run_state.pop();
+            return;
+        }
+    };
+
+    let (board_entity, board) = boards.single();
+    let live = GameState::from_tiles(
+        board.size,
+        tiles
+            .iter()
+            .map(|(_, pos, points)| ((pos.x, pos.y), points.value)),
+    );
+    for (entity, _, _) in tiles.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if board.size != replay.board_size {
+        commands.entity(board_entity).despawn_recursive();
+        spawn_board_entity(
+            &mut commands,
+            Board::fit_to_window(replay.board_size, &windows),
+        );
+    }
+
+    let mut viewer = ReplayViewer {
+        frames: replay.frames(),
+        index: 0,
+        shown: None,
+        playing: true,
+        speed: DEFAULT_SPEED,
+        timer: Timer::from_seconds(STEP_SECONDS, true),
+        live,
+    };
+    viewer.set_speed(DEFAULT_SPEED);
+    commands.insert_resource(viewer);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(20.0),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::NONE),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for (control, label, width) in [
+                (ReplayControl::Back, "Back", 80.0),
+                (ReplayControl::PlayPause, "Pause", 80.0),
+                (ReplayControl::Forward, "Next", 80.0),
+                (ReplayControl::Slower, "Slower", 80.0),
+                (ReplayControl::Faster, "Faster", 80.0),
+                (ReplayControl::Exit, "Exit", 80.0),
+            ] {
+                spawn_button(parent, &font_spec, label, width).insert(control);
+            }
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: font_spec.family.clone(),
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    style: Style {
+                        margin: UiRect::all(Val::Px(10.0)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(ReplayStatusText)
+                .insert(ThemeColor::Text);
+        })
+        .insert(ReplayControls);
+}
+
+fn apply_control(
+    control: ReplayControl,
+    viewer: &mut ReplayViewer,
+    run_state: &mut State<RunState>,
+) {
+    match control {
+        ReplayControl::Back => {
+            viewer.playing = false;
+            viewer.step_back();
+        }
+        ReplayControl::PlayPause => {
+            // playing from the end starts over
+            if !viewer.playing && viewer.index + 1 == viewer.frames.len() {
+                viewer.index = 0;
+            }
+            viewer.playing = !viewer.playing;
+            viewer.timer.reset();
+        }
+        ReplayControl::Forward => {
+            viewer.playing = false;
+            viewer.step_forward();
+        }
+        ReplayControl::Slower => {
+            viewer.set_speed(viewer.speed.saturating_sub(1))
+        }
+        ReplayControl::Faster => viewer.set_speed(viewer.speed + 1),
+        ReplayControl::Exit => {
+            let _ = run_state.pop();
+        }
+    }
+}
+
+fn replay_controls(
+    interaction_query: Query<
+        (&Interaction, &ReplayControl),
+        Changed<Interaction>,
+    >,
+    mut viewer: ResMut<ReplayViewer>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    for (interaction, control) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            apply_control(*control, &mut viewer, &mut run_state);
+        }
+    }
+}
+
+/// The viewer has its own fixed keys: arrows step and change speed,
+/// space plays and pauses, escape leaves.
+fn replay_keys(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut viewer: ResMut<ReplayViewer>,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    let keys = [
+        (KeyCode::Left, ReplayControl::Back),
+        (KeyCode::Right, ReplayControl::Forward),
+        (KeyCode::Space, ReplayControl::PlayPause),
+        (KeyCode::Down, ReplayControl::Slower),
+        (KeyCode::Up, ReplayControl::Faster),
+        (KeyCode::Escape, ReplayControl::Exit),
+    ];
+    for (key, control) in keys {
+        if !keyboard_input.just_pressed(key) {
+            continue;
+        }
+        apply_control(control, &mut viewer, &mut run_state);
+        if let ReplayControl::Exit = control {
+            // the state underneath runs within this frame and would
+            // take escape as a pause, or the arrows as moves
+            for (key, _) in keys {
+                keyboard_input.reset(key);
+            }
+            return;
+        }
+    }
+}
+
+fn replay_timer(time: Res<Time>, mut viewer: ResMut<ReplayViewer>) {
+    if !viewer.playing {
+        return;
+    }
+    viewer.timer.tick(time.delta());
+    for _ in 0..viewer.timer.times_finished() {
+        viewer.step_forward();
+    }
+}
+
+/// Brings the tiles in line with the current frame, keeping the ones
+/// that are already right so only what changed animates.
+fn show_replay_frame(
+    mut commands: Commands,
+    mut viewer: ResMut<ReplayViewer>,
+    font_spec: Res<FontSpec>,
+    query_board: Query<&Board>,
+    tiles: Query<(Entity, &Position, &Points)>,
+    mut status: Query<&mut Text, With<ReplayStatusText>>,
+    buttons: Query<(&Children, &ReplayControl)>,
+    mut texts: Query<&mut Text, Without<ReplayStatusText>>,
+) {
+    for (children, control) in buttons.iter() {
+        if let ReplayControl::PlayPause = control {
+            let mut text = texts.get_mut(children[0]).unwrap();
+            let label = if viewer.playing { "Pause" } else { "Play" };
+            if text.sections[0].value != label {
+                text.sections[0].value = label.to_string();
+            }
+        }
+    }
+    let (state, score) = &viewer.frames[viewer.index];
+    let line = format!(
+        "move {}/{}  score {}  {}x",
+        viewer.index,
+        viewer.frames.len() - 1,
+        score,
+        SPEEDS[viewer.speed]
+    );
+    let mut status = status.single_mut();
+    if status.sections[0].value != line {
+        status.sections[0].value = line;
+    }
+
+    if viewer.shown == Some(viewer.index) {
+        return;
+    }
+    let board = query_board.single();
+    let mut kept = HashSet::new();
+    for (entity, pos, points) in tiles.iter() {
+        if state.get(pos.x, pos.y) == Some(points.value) {
+            kept.insert((pos.x, pos.y));
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for ((x, y), value) in state.tiles() {
+        if !kept.contains(&(x, y)) {
+            spawn_tile(&mut commands, board, &font_spec, Position { x, y }, value);
+        }
+    }
+    viewer.shown = Some(viewer.index);
+}
+
+fn exit_replay(
+    mut commands: Commands,
+    viewer: Res<ReplayViewer>,
+    windows: Res<Windows>,
+    font_spec: Res<FontSpec>,
+    boards: Query<(Entity, &Board)>,
+    tiles: Query<Entity, With<Position>>,
+    controls: Query<Entity, With<ReplayControls>>,
+) {
+    for entity in tiles.iter().chain(controls.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let (board_entity, board) = boards.single();
+    let live = viewer.live();
+    let board = if board.size == live.size() {
+        board.clone()
+    } else {
+        commands.entity(board_entity).despawn_recursive();
+        let board = Board::fit_to_window(live.size(), &windows);
+        spawn_board_entity(&mut commands, board.clone());
+        board
+    };
+    for ((x, y), value) in live.tiles() {
+        spawn_tile(&mut commands, &board, &font_spec, Position { x, y }, value);
+    }
+    commands.remove_resource::<ReplayViewer>();
+}
@@ -0,0 +1,120 @@
+use std::env;
+
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+const SEED_FLAG: &str = "--seed";
+const SEED_VAR: &str = "BOXES_SEED";
+
+/// The single source of randomness for tile placement.
+///
+/// Every game is played from its own seed. The first one comes from
+/// the command line or environment when given; the ones after it are
+/// drawn from the previous game's stream, so a whole session is
+/// reproducible from the first seed and any single game from its own.
+pub struct GameRng {
+    seed: u64,
+    next_seed: Option<u64>,
+    rng: ChaCha12Rng,
+}
+
+/// Enough to put a `GameRng` back exactly where it was: the game's
+/// seed plus how far into its stream we have drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngState {
+    seed: u64,
+    next_seed: Option<u64>,
+    word_pos: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed =
+            seed.unwrap_or_else(|| ChaCha12Rng::from_entropy().gen());
+        GameRng {
+            seed,
+            next_seed: Some(seed),
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn from_state(state: RngState) -> Self {
+        let mut rng = ChaCha12Rng::seed_from_u64(state.seed);
+        rng.set_word_pos(u128::from(state.word_pos));
+        GameRng {
+            seed: state.seed,
+            next_seed: state.next_seed,
+            rng,
+        }
+    }
+
+    pub fn state(&self) -> RngState {
+        RngState {
+            seed: self.seed,
+            next_seed: self.next_seed,
+            word_pos: self.rng.get_word_pos() as u64,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Reseeds for a new game.
+    pub fn start_game(&mut self) {
+        let seed =
+            self.next_seed.take().unwrap_or_else(|| self.rng.gen());
+        self.seed = seed;
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(
+        &mut self,
+        dest: &mut [u8],
+    ) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Reads `--seed <n>` / `--seed=<n>` from the arguments, falling back
+/// to the `BOXES_SEED` environment variable. A seed that was given but
+/// isn't a number is an error rather than a quietly random game.
+pub fn seed_from_args() -> Result<Option<u64>, String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == SEED_FLAG {
+            let seed = args
+                .next()
+                .ok_or_else(|| format!("{} needs a number", SEED_FLAG))?;
+            return parse_seed(&seed, SEED_FLAG).map(Some);
+        }
+        if let Some(seed) = arg.strip_prefix("--seed=") {
+            return parse_seed(seed, SEED_FLAG).map(Some);
+        }
+    }
+    match env::var(SEED_VAR) {
+        Ok(seed) => parse_seed(&seed, SEED_VAR).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_seed(seed: &str, source: &str) -> Result<u64, String> {
+    seed.parse().map_err(|_| {
+        format!("{} needs a number, got {:?}", source, seed)
+    })
+}
@@ -0,0 +1,394 @@
+//! The slide and merge rules of the game, independent of Bevy.
+//!
+//! `GameState` knows nothing about entities; the systems in `main.rs`
+//! build one from the board, shift it, and apply the returned
+//! `ShiftOutcome` back onto the tiles.
+
+use std::fmt;
+
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum BoardShift {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl BoardShift {
+    pub const ALL: [BoardShift; 4] = [
+        BoardShift::Left,
+        BoardShift::Right,
+        BoardShift::Up,
+        BoardShift::Down,
+    ];
+
+    /// Maps a line (row or column) and a distance from the wall
+    /// the tiles are pushed against to board coordinates.
+    fn cell(&self, size: u8, line: u8, index: u8) -> (u8, u8) {
+        match self {
+            BoardShift::Left => (index, line),
+            BoardShift::Right => (size - 1 - index, line),
+            BoardShift::Up => (line, size - 1 - index),
+            BoardShift::Down => (line, index),
+        }
+    }
+}
+
+/// A surviving tile that ended up in a different cell.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TileMove {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+}
+
+/// Two tiles combined into one. `survivor` keeps existing with the
+/// new `value`, `consumed` slides into `to` and goes away.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TileMerge {
+    pub survivor: (u8, u8),
+    pub consumed: (u8, u8),
+    pub to: (u8, u8),
+    pub value: u32,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ShiftOutcome {
+    pub moves: Vec<TileMove>,
+    pub merges: Vec<TileMerge>,
+    pub score_delta: u32,
+    pub changed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GameState {
+    size: u8,
+    cells: Vec<Option<u32>>,
+}
+
+impl GameState {
+    pub fn new(size: u8) -> Self {
+        GameState {
+            size,
+            cells: vec![None; usize::from(size) * usize::from(size)],
+        }
+    }
+
+    pub fn from_tiles(
+        size: u8,
+        tiles: impl IntoIterator<Item = ((u8, u8), u32)>,
+    ) -> Self {
+        let mut state = GameState::new(size);
+        for ((x, y), value) in tiles {
+            state.set(x, y, Some(value));
+        }
+        state
+    }
+
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    fn index(&self, x: u8, y: u8) -> usize {
+        usize::from(y) * usize::from(self.size) + usize::from(x)
+    }
+
+    pub fn get(&self, x: u8, y: u8) -> Option<u32> {
+        self.cells[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: u8, y: u8, value: Option<u32>) {
+        let index = self.index(x, y);
+        self.cells[index] = value;
+    }
+
+    /// Every occupied cell with its value, row by row.
+    pub fn tiles(&self) -> impl Iterator<Item = ((u8, u8), u32)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(i, cell)| {
+            cell.map(|value| {
+                let x = (i % usize::from(self.size)) as u8;
+                let y = (i / usize::from(self.size)) as u8;
+                ((x, y), value)
+            })
+        })
+    }
+
+    /// Empty cells column by column, the order every random
+    /// placement draws from.
+    pub fn empty_cells(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        (0..self.size).flat_map(move |x| {
+            (0..self.size)
+                .filter(move |y| self.get(x, *y).is_none())
+                .map(move |y| (x, y))
+        })
+    }
+
+    /// Places `count` tiles on distinct random empty cells, as at the
+    /// start of a game.
+    pub fn deal<R: Rng + ?Sized>(
+        &mut self,
+        count: usize,
+        distribution: &TileDistribution,
+        rng: &mut R,
+    ) -> Vec<((u8, u8), u32)> {
+        let cells = self.empty_cells().choose_multiple(rng, count);
+        cells
+            .into_iter()
+            .map(|(x, y)| {
+                let value = distribution.sample(rng);
+                self.set(x, y, Some(value));
+                ((x, y), value)
+            })
+            .collect()
+    }
+
+    /// Places one tile on a random empty cell, as after every move.
+    pub fn spawn_random<R: Rng + ?Sized>(
+        &mut self,
+        distribution: &TileDistribution,
+        rng: &mut R,
+    ) -> Option<((u8, u8), u32)> {
+        let (x, y) = self.empty_cells().choose(rng)?;
+        let value = distribution.sample(rng);
+        self.set(x, y, Some(value));
+        Some(((x, y), value))
+    }
+
+    /// Whether any shift would still change the board.
+    pub fn has_moves(&self) -> bool {
+        (0..self.size).any(|x| {
+            (0..self.size).any(|y| match self.get(x, y) {
+                None => true,
+                Some(value) => {
+                    (x + 1 < self.size && self.get(x + 1, y) == Some(value))
+                        || (y + 1 < self.size
+                            && self.get(x, y + 1) == Some(value))
+                }
+            })
+        })
+    }
+
+    pub fn shift(&mut self, board_shift: BoardShift) -> ShiftOutcome {
+        let mut outcome = ShiftOutcome::default();
+        let mut cells = vec![None; self.cells.len()];
+
+        for line in 0..self.size {
+            let mut line_tiles = (0..self.size)
+                .map(|index| board_shift.cell(self.size, line, index))
+                .filter_map(|(x, y)| {
+                    self.get(x, y).map(|value| ((x, y), value))
+                })
+                .peekable();
+
+            let mut column: u8 = 0;
+            while let Some((from, value)) = line_tiles.next() {
+                let to = board_shift.cell(self.size, line, column);
+                let next_matches = line_tiles
+                    .peek()
+                    .map_or(false, |(_, next)| *next == value);
+
+                if next_matches {
+                    let (consumed, _) = line_tiles
+                        .next()
+                        .expect("a peeked tile should always exist when we .next");
+                    let merged = value + value;
+                    outcome.merges.push(TileMerge {
+                        survivor: from,
+                        consumed,
+                        to,
+                        value: merged,
+                    });
+                    outcome.score_delta += merged;
+                    cells[self.index(to.0, to.1)] = Some(merged);
+                } else {
+                    cells[self.index(to.0, to.1)] = Some(value);
+                }
+
+                if from != to {
+                    outcome.moves.push(TileMove { from, to });
+                }
+                column += 1;
+            }
+        }
+
+        outcome.changed =
+            !outcome.moves.is_empty() || !outcome.merges.is_empty();
+        self.cells = cells;
+        outcome
+    }
+}
+
+/// Draws the board top row first, one right-aligned column per cell
+/// and `.` for empty cells.
+impl fmt::Display for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .cells
+            .iter()
+            .flatten()
+            .map(|value| value.to_string().len())
+            .max()
+            .unwrap_or(1);
+        for y in (0..self.size).rev() {
+            let row: Vec<String> = (0..self.size)
+                .map(|x| match self.get(x, y) {
+                    Some(value) => format!("{:>width$}", value, width = width),
+                    None => format!("{:>width$}", ".", width = width),
+                })
+                .collect();
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The values a freshly spawned tile can take, with relative weights.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TileDistribution {
+    pub weights: Vec<(u32, u32)>,
+}
+
+impl Default for TileDistribution {
+    fn default() -> Self {
+        TileDistribution {
+            weights: vec![(2, 9), (4, 1)],
+        }
+    }
+}
+
+impl TileDistribution {
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u32 {
+        let index = WeightedIndex::new(
+            self.weights.iter().map(|(_, weight)| *weight),
+        )
+        .expect("tile distribution needs at least one positive weight");
+        self.weights[index.sample(rng)].0
+    }
+
+    /// Each value with the chance of it being the one spawned.
+    pub fn probabilities(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        let total: u32 = self.weights.iter().map(|(_, weight)| weight).sum();
+        self.weights
+            .iter()
+            .map(move |(value, weight)| (*value, *weight as f32 / total as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: [Option<u32>; 4]) -> GameState {
+        GameState::from_tiles(
+            4,
+            values.iter().enumerate().filter_map(|(x, value)| {
+                value.map(|value| ((x as u8, 0), value))
+            }),
+        )
+    }
+
+    fn values_in_row(state: &GameState) -> Vec<Option<u32>> {
+        (0..4).map(|x| state.get(x, 0)).collect()
+    }
+
+    #[test]
+    fn four_equal_tiles_merge_into_two() {
+        let mut state = row([Some(2), Some(2), Some(2), Some(2)]);
+        let outcome = state.shift(BoardShift::Left);
+
+        assert_eq!(values_in_row(&state), vec![Some(4), Some(4), None, None]);
+        assert_eq!(outcome.merges.len(), 2);
+        assert_eq!(outcome.score_delta, 8);
+        assert!(outcome.changed);
+    }
+
+    #[test]
+    fn merged_tile_does_not_merge_again() {
+        let mut state = row([Some(2), Some(2), Some(4), None]);
+        let outcome = state.shift(BoardShift::Left);
+
+        assert_eq!(values_in_row(&state), vec![Some(4), Some(4), None, None]);
+        assert_eq!(
+            outcome.merges,
+            vec![TileMerge {
+                survivor: (0, 0),
+                consumed: (1, 0),
+                to: (0, 0),
+                value: 4,
+            }]
+        );
+        assert_eq!(
+            outcome.moves,
+            vec![TileMove {
+                from: (2, 0),
+                to: (1, 0),
+            }]
+        );
+    }
+
+    #[test]
+    fn cell_maps_lines_against_each_wall() {
+        assert_eq!(BoardShift::Left.cell(4, 1, 0), (0, 1));
+        assert_eq!(BoardShift::Left.cell(4, 1, 3), (3, 1));
+        assert_eq!(BoardShift::Right.cell(4, 1, 0), (3, 1));
+        assert_eq!(BoardShift::Right.cell(4, 1, 3), (0, 1));
+        assert_eq!(BoardShift::Up.cell(4, 1, 0), (1, 3));
+        assert_eq!(BoardShift::Up.cell(4, 1, 3), (1, 0));
+        assert_eq!(BoardShift::Down.cell(4, 1, 0), (1, 0));
+        assert_eq!(BoardShift::Down.cell(4, 1, 3), (1, 3));
+    }
+
+    #[test]
+    fn each_direction_pushes_toward_its_wall() {
+        let start = GameState::from_tiles(4, [((1, 1), 2)]);
+        for (board_shift, to) in [
+            (BoardShift::Left, (0, 1)),
+            (BoardShift::Right, (3, 1)),
+            (BoardShift::Up, (1, 3)),
+            (BoardShift::Down, (1, 0)),
+        ] {
+            let mut state = start.clone();
+            let outcome = state.shift(board_shift);
+            assert_eq!(state, GameState::from_tiles(4, [(to, 2)]));
+            assert_eq!(outcome.moves, vec![TileMove { from: (1, 1), to }]);
+        }
+    }
+
+    #[test]
+    fn blocked_shift_changes_nothing() {
+        let mut state = row([Some(2), Some(4), Some(8), None]);
+        let before = state.clone();
+        let outcome = state.shift(BoardShift::Left);
+
+        assert!(!outcome.changed);
+        assert!(outcome.moves.is_empty());
+        assert!(outcome.merges.is_empty());
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn full_board_has_moves_only_with_equal_neighbours() {
+        let stuck = GameState::from_tiles(
+            4,
+            (0..4u8).flat_map(|x| {
+                (0..4u8).map(move |y| {
+                    ((x, y), if (x + y) % 2 == 0 { 2 } else { 4 })
+                })
+            }),
+        );
+        assert!(!stuck.has_moves());
+        for board_shift in BoardShift::ALL {
+            assert!(!stuck.clone().shift(board_shift).changed);
+        }
+
+        let mut mergeable = stuck.clone();
+        mergeable.set(1, 0, Some(2));
+        assert!(mergeable.has_moves());
+
+        let mut open = stuck;
+        open.set(2, 2, None);
+        assert!(open.has_moves());
+    }
+}
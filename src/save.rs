@@ -0,0 +1,197 @@
+use bevy::{app::AppExit, prelude::*};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    persist,
+    replay::{Replay, ReplayRecorder, ReplayViewer},
+    rng::{GameRng, RngState},
+    spawn_tile, Board, FontSpec, Game, GameSettings, Points,
+    Position, RunState, MAX_BOARD_SIZE, MIN_BOARD_SIZE,
+};
+
+const SAVE_FILE: &str = "save.ron";
+/// Bump whenever `SavedGame` changes shape; older saves are dropped
+/// instead of being half-restored.
+const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedGame {
+    version: u32,
+    board_size: u8,
+    score: u32,
+    moves: u32,
+    won: bool,
+    #[serde(default)]
+    hints_used: u32,
+    #[serde(default)]
+    merges: u32,
+    #[serde(default)]
+    seconds: f32,
+    tiles: Vec<((u8, u8), u32)>,
+    rng: RngState,
+    #[serde(default)]
+    replay: Option<Replay>,
+}
+
+/// A game read from disk at launch, picked up by the first
+/// `spawn_tiles` instead of dealing a fresh board.
+#[derive(Default)]
+pub struct PendingRestore(Option<SavedGame>);
+
+impl PendingRestore {
+    pub fn take(&mut self) -> Option<SavedGame> {
+        self.0.take()
+    }
+
+    pub fn has_game(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn board_size(&self) -> Option<u8> {
+        self.0.as_ref().map(SavedGame::board_size)
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingRestore>()
+            // the board is spawned during `Startup` and has to be
+            // the saved size already
+            .add_startup_system_to_stage(
+                StartupStage::PreStartup,
+                load_saved_game,
+            )
+            .add_system_to_stage(CoreStage::Last, save_game_on_exit);
+    }
+}
+
+impl SavedGame {
+    pub fn board_size(&self) -> u8 {
+        self.board_size
+    }
+
+    fn is_valid(&self) -> bool {
+        let size = self.board_size;
+        (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&size)
+            && self.tiles.iter().all(|((x, y), value)| {
+                *x < size && *y < size && *value > 1 && value.is_power_of_two()
+            })
+            && self.tiles.iter().map(|(pos, _)| pos).all_unique()
+    }
+
+    pub fn restore(
+        self,
+        commands: &mut Commands,
+        board: &Board,
+        font_spec: &Res<FontSpec>,
+        game: &mut Game,
+        rng: &mut GameRng,
+        recorder: &mut ReplayRecorder,
+    ) {
+        for ((x, y), value) in self.tiles {
+            spawn_tile(commands, board, font_spec, Position { x, y }, value);
+        }
+        game.score = self.score;
+        game.moves = self.moves;
+        game.won = self.won;
+        game.hints_used = self.hints_used;
+        game.merges = self.merges;
+        game.seconds = self.seconds;
+        *rng = GameRng::from_state(self.rng);
+        recorder.resume(self.replay);
+        info!("resumed saved game with seed {}", rng.seed());
+    }
+}
+
+fn load_saved_game(
+    mut pending: ResMut<PendingRestore>,
+    mut settings: ResMut<GameSettings>,
+) {
+    let saved = match persist::load::<SavedGame>(SAVE_FILE) {
+        Ok(Some(saved)) => saved,
+        Ok(None) => return,
+        Err(error) => {
+            warn!("discarding unreadable saved game: {}", error);
+            return;
+        }
+    };
+    if saved.version != SAVE_VERSION {
+        warn!(
+            "discarding saved game from version {}, expected {}",
+            saved.version, SAVE_VERSION
+        );
+        return;
+    }
+    if !saved.is_valid() {
+        warn!("discarding saved game with an impossible board");
+        return;
+    }
+
+    settings.board_size = saved.board_size;
+    pending.0 = Some(saved);
+}
+
+/// A finished game leaves nothing to resume, so its save is removed
+/// rather than written.
+fn save_game_on_exit(
+    mut exit_reader: EventReader<AppExit>,
+    run_state: Res<State<RunState>>,
+    game: Res<Game>,
+    query_board: Query<&Board>,
+    tiles: Query<(&Position, &Points)>,
+    rng: Res<GameRng>,
+    recorder: Res<ReplayRecorder>,
+    viewer: Option<Res<ReplayViewer>>,
+) {
+    if exit_reader.iter().last().is_none() {
+        return;
+    }
+    // no game has started, so any save on disk is still untouched
+    if *run_state.current() == RunState::MainMenu
+        || run_state.inactives().contains(&RunState::MainMenu)
+    {
+        return;
+    }
+
+    // settings may be open on top of the finished game
+    let game_over = *run_state.current() == RunState::GameOver
+        || run_state.inactives().contains(&RunState::GameOver);
+    let result = if game_over {
+        persist::remove(SAVE_FILE)
+    } else {
+        // a replay on screen has the game's own tiles put away
+        let (board_size, tiles) = match &viewer {
+            Some(viewer) => (
+                viewer.live().size(),
+                viewer.live().tiles().collect(),
+            ),
+            None => (
+                query_board.single().size,
+                tiles
+                    .iter()
+                    .map(|(pos, points)| ((pos.x, pos.y), points.value))
+                    .collect(),
+            ),
+        };
+        let saved = SavedGame {
+            version: SAVE_VERSION,
+            board_size,
+            score: game.score,
+            moves: game.moves,
+            won: game.won,
+            hints_used: game.hints_used,
+            merges: game.merges,
+            seconds: game.seconds,
+            tiles,
+            rng: rng.state(),
+            replay: recorder.replay().cloned(),
+        };
+        persist::save(SAVE_FILE, &saved)
+    };
+    if let Err(error) = result {
+        warn!("could not save the game: {}", error);
+    }
+}
@@ -0,0 +1,302 @@
+//! Plays games on the rules engine alone, without a window or GPU, so
+//! scripts and CI can drive the game from text.
+
+use std::{
+    env, fs,
+    io::{self, Read},
+};
+
+use crate::{
+    ai::{load_ai_config, AiConfig},
+    rng::{seed_from_args, GameRng},
+    rules::{BoardShift, GameState, TileDistribution},
+    GameSettings, MAX_BOARD_SIZE, MIN_BOARD_SIZE, STARTING_TILES,
+};
+
+const HEADLESS_FLAG: &str = "--headless";
+
+pub fn headless_requested() -> bool {
+    env::args().skip(1).any(|arg| arg == HEADLESS_FLAG)
+}
+
+struct SimOptions {
+    board_size: u8,
+    moves_file: Option<String>,
+    trace: bool,
+    /// Let the bot play this many games instead of reading moves.
+    benchmark: Option<u32>,
+    depth: Option<u8>,
+    seed: Option<u64>,
+}
+
+impl SimOptions {
+    fn from_args() -> Result<Self, String> {
+        let mut options = SimOptions {
+            board_size: GameSettings::default().board_size,
+            moves_file: None,
+            trace: false,
+            benchmark: None,
+            depth: None,
+            seed: seed_from_args()?,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => {
+                    let size = args
+                        .next()
+                        .and_then(|size| size.parse().ok())
+                        .filter(|size| {
+                            (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(size)
+                        })
+                        .ok_or_else(|| {
+                            format!(
+                                "--size needs a number from {} to {}",
+                                MIN_BOARD_SIZE, MAX_BOARD_SIZE
+                            )
+                        })?;
+                    options.board_size = size;
+                }
+                "--moves" => {
+                    options.moves_file = Some(
+                        args.next().ok_or("--moves needs a file name")?,
+                    );
+                }
+                "--trace" => options.trace = true,
+                "--benchmark" => {
+                    options.benchmark = Some(
+                        args.next()
+                            .and_then(|games| games.parse().ok())
+                            .filter(|games| *games > 0)
+                            .ok_or("--benchmark needs a number of games")?,
+                    );
+                }
+                "--depth" => {
+                    options.depth = Some(
+                        args.next()
+                            .and_then(|depth| depth.parse().ok())
+                            .filter(|depth| *depth > 0)
+                            .ok_or("--depth needs a positive number")?,
+                    );
+                }
+                // read by `seed_from_args`
+                "--seed" => {
+                    args.next();
+                }
+                _ if arg.starts_with("--seed=") => {}
+                HEADLESS_FLAG => {}
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Moves are whitespace separated words (`left`, `up`, ...) or runs of
+/// their initials (`lurd`), in any case. `#` starts a comment.
+fn parse_moves(input: &str) -> Result<Vec<BoardShift>, String> {
+    let mut moves = vec![];
+    for (line_number, line) in input.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        for word in line.split_whitespace() {
+            let word = word.to_lowercase();
+            match word.as_str() {
+                "left" => moves.push(BoardShift::Left),
+                "right" => moves.push(BoardShift::Right),
+                "up" => moves.push(BoardShift::Up),
+                "down" => moves.push(BoardShift::Down),
+                _ => {
+                    for initial in word.chars() {
+                        moves.push(match initial {
+                            'l' => BoardShift::Left,
+                            'r' => BoardShift::Right,
+                            'u' => BoardShift::Up,
+                            'd' => BoardShift::Down,
+                            _ => {
+                                return Err(format!(
+                                    "line {}: unknown move {:?}",
+                                    line_number + 1,
+                                    word
+                                ))
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(moves)
+}
+
+pub fn run() -> Result<(), String> {
+    let options = SimOptions::from_args()?;
+    if let Some(games) = options.benchmark {
+        let mut config = load_ai_config();
+        if let Some(depth) = options.depth {
+            config.depth = depth;
+        }
+        benchmark(&options, &config, games);
+        return Ok(());
+    }
+
+    let input = match &options.moves_file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|error| format!("could not read {}: {}", path, error))?,
+        None => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|error| format!("could not read stdin: {}", error))?;
+            input
+        }
+    };
+    let moves = parse_moves(&input)?;
+
+    let distribution = TileDistribution::default();
+    let mut rng = GameRng::new(options.seed);
+    rng.start_game();
+    let mut state = GameState::new(options.board_size);
+    state.deal(STARTING_TILES, &distribution, &mut rng);
+
+    println!("seed {}", rng.seed());
+    if options.trace {
+        print!("{}", state);
+    }
+
+    let mut score = 0;
+    let mut move_count = 0;
+    for board_shift in moves {
+        if !state.has_moves() {
+            break;
+        }
+        let outcome = state.shift(board_shift);
+        if !outcome.changed {
+            continue;
+        }
+        score += outcome.score_delta;
+        move_count += 1;
+        state.spawn_random(&distribution, &mut rng);
+        if options.trace {
+            println!("{:?}", board_shift);
+            print!("{}", state);
+        }
+    }
+
+    if !options.trace {
+        print!("{}", state);
+    }
+    println!("score {}", score);
+    println!("moves {}", move_count);
+    if !state.has_moves() {
+        println!("game over");
+    }
+    Ok(())
+}
+
+/// Has the bot play `games` games in a row and reports how often it
+/// reaches the win target. Each game is seeded from the previous one,
+/// so a run is reproducible from `--seed`.
+fn benchmark(options: &SimOptions, config: &AiConfig, games: u32) {
+    let win_target = GameSettings::default().win_target;
+    let distribution = TileDistribution::default();
+    let mut rng = GameRng::new(options.seed);
+    let mut wins = 0;
+    let mut total_score = 0;
+
+    for game in 1..=games {
+        rng.start_game();
+        let seed = rng.seed();
+        let mut state = GameState::new(options.board_size);
+        state.deal(STARTING_TILES, &distribution, &mut rng);
+
+        let mut score = 0;
+        let mut move_count = 0;
+        let mut max_tile = 0;
+        while max_tile < win_target {
+            let board_shift = match config.best_move(&state, &distribution) {
+                Some(board_shift) => board_shift,
+                None => break,
+            };
+            score += state.shift(board_shift).score_delta;
+            move_count += 1;
+            state.spawn_random(&distribution, &mut rng);
+            max_tile = state.tiles().map(|(_, value)| value).max().unwrap_or(0);
+        }
+
+        let won = max_tile >= win_target;
+        if won {
+            wins += 1;
+        }
+        total_score += u64::from(score);
+        println!(
+            "game {} seed {} score {} moves {} max {} {}",
+            game,
+            seed,
+            score,
+            move_count,
+            max_tile,
+            if won { "won" } else { "lost" }
+        );
+        if options.trace {
+            print!("{}", state);
+        }
+    }
+
+    println!(
+        "won {}/{} ({:.1}%) at depth {}, average score {}",
+        wins,
+        games,
+        f64::from(wins) * 100.0 / f64::from(games),
+        config.depth,
+        total_score / u64::from(games)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_moves_reads_words_in_any_case() {
+        assert_eq!(
+            parse_moves("left Up\nRIGHT   down"),
+            Ok(vec![
+                BoardShift::Left,
+                BoardShift::Up,
+                BoardShift::Right,
+                BoardShift::Down,
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_moves_reads_runs_of_initials() {
+        assert_eq!(
+            parse_moves("lurd LL"),
+            Ok(vec![
+                BoardShift::Left,
+                BoardShift::Up,
+                BoardShift::Right,
+                BoardShift::Down,
+                BoardShift::Left,
+                BoardShift::Left,
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_moves_skips_comments() {
+        assert_eq!(
+            parse_moves("# opening\nup # then\n#left\ndown"),
+            Ok(vec![BoardShift::Up, BoardShift::Down])
+        );
+    }
+
+    #[test]
+    fn parse_moves_reports_the_line_of_a_bad_move() {
+        assert_eq!(
+            parse_moves("up\n# fine\nleft sideways"),
+            Err("line 3: unknown move \"sideways\"".to_string())
+        );
+    }
+}
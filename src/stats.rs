@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    colors::ThemeColor,
+    game_reset,
+    menus::{despawn_with, label, overlay, row},
+    persist,
+    ui::spawn_button,
+    FontSpec, Game, Points, RunState,
+};
+
+const STATS_FILE: &str = "stats.ron";
+
+/// Totals over every game finished on this machine.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LifetimeStats {
+    pub games_won: u32,
+    pub games_lost: u32,
+    pub moves: u64,
+    pub merges: u64,
+    pub highest_tile: u32,
+    pub seconds_played: f64,
+    pub hints_used: u32,
+    /// How many games ended with each tile as their largest.
+    pub final_max_tiles: BTreeMap<u32, u32>,
+}
+
+impl LifetimeStats {
+    fn record(&mut self, game: &Game, max_tile: u32) {
+        if game.won {
+            self.games_won += 1;
+        } else {
+            self.games_lost += 1;
+        }
+        self.moves += u64::from(game.moves);
+        self.merges += u64::from(game.merges);
+        self.highest_tile = self.highest_tile.max(max_tile);
+        self.seconds_played += f64::from(game.seconds);
+        self.hints_used += game.hints_used;
+        *self.final_max_tiles.entry(max_tile).or_insert(0) += 1;
+    }
+}
+
+/// The totals from just before the current game was added, so an
+/// undo out of `GameOver` can take it back out.
+#[derive(Default)]
+struct StatsBeforeGame(Option<LifetimeStats>);
+
+/// `h:mm:ss`, or `m:ss` under an hour.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    let (hours, minutes, seconds) =
+        (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[derive(Component)]
+pub struct StatsScreen;
+
+#[derive(Component)]
+pub struct StatsBackButton;
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LifetimeStats>()
+            .init_resource::<StatsBeforeGame>()
+            .add_startup_system(load_stats)
+            .add_system_set(
+                SystemSet::on_update(RunState::Playing)
+                    .with_system(count_play_time),
+            )
+            .add_system_set(
+                SystemSet::on_enter(RunState::GameOver)
+                    .with_system(record_finished_game),
+            )
+            // restarting or starting over from a win never passes
+            // through `GameOver`
+            .add_system_set(
+                SystemSet::on_enter(RunState::Playing)
+                    .with_system(record_finished_game.before(game_reset)),
+            )
+            // only an undo pops `GameOver` back to the game
+            .add_system_set(
+                SystemSet::on_resume(RunState::Playing)
+                    .with_system(unrecord_undone_game),
+            )
+            .add_system_set(
+                SystemSet::on_enter(RunState::Stats)
+                    .with_system(spawn_stats_screen),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Stats)
+                    .with_system(stats_back_button),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Stats)
+                    .with_system(despawn_with::<StatsScreen>),
+            );
+    }
+}
+
+fn count_play_time(time: Res<Time>, mut game: ResMut<Game>) {
+    game.seconds += time.delta_seconds();
+}
+
+/// Adds the game that just ended to the lifetime totals, once. A game
+/// nobody moved in doesn't count.
+fn record_finished_game(
+    mut game: ResMut<Game>,
+    mut lifetime: ResMut<LifetimeStats>,
+    mut before: ResMut<StatsBeforeGame>,
+    tiles: Query<&Points>,
+) {
+    if game.stats_recorded || game.moves == 0 {
+        return;
+    }
+    let max_tile = tiles.iter().map(|points| points.value).max().unwrap_or(0);
+    before.0 = Some(lifetime.clone());
+    lifetime.record(&game, max_tile);
+    game.stats_recorded = true;
+    save_stats(&lifetime);
+}
+
+/// An undone last move means the game goes on, so it comes back out
+/// of the totals and is recorded again whenever it really ends.
+fn unrecord_undone_game(
+    mut game: ResMut<Game>,
+    mut lifetime: ResMut<LifetimeStats>,
+    mut before: ResMut<StatsBeforeGame>,
+) {
+    if !game.stats_recorded {
+        return;
+    }
+    if let Some(before) = before.0.take() {
+        *lifetime = before;
+        game.stats_recorded = false;
+        save_stats(&lifetime);
+    }
+}
+
+fn save_stats(lifetime: &LifetimeStats) {
+    if let Err(error) = persist::save(STATS_FILE, lifetime) {
+        warn!("could not save stats: {}", error);
+    }
+}
+
+fn load_stats(mut lifetime: ResMut<LifetimeStats>) {
+    match persist::load::<LifetimeStats>(STATS_FILE) {
+        Ok(Some(loaded)) => *lifetime = loaded,
+        Ok(None) => {}
+        Err(error) => warn!("could not load stats: {}", error),
+    }
+}
+
+fn spawn_stats_screen(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+    game: Res<Game>,
+    lifetime: Res<LifetimeStats>,
+    tiles: Query<&Points>,
+) {
+    let max_tile = tiles.iter().map(|points| points.value).max().unwrap_or(0);
+    let this_game = [
+        ("Score", game.score.to_string()),
+        ("Moves", game.moves.to_string()),
+        ("Merges", game.merges.to_string()),
+        ("Highest tile", max_tile.to_string()),
+        ("Time", format_duration(f64::from(game.seconds))),
+        ("Hints", game.hints_used.to_string()),
+    ];
+    let games = lifetime.games_won + lifetime.games_lost;
+    let all_games = [
+        ("Games", games.to_string()),
+        ("Won", lifetime.games_won.to_string()),
+        ("Lost", lifetime.games_lost.to_string()),
+        ("Moves", lifetime.moves.to_string()),
+        ("Merges", lifetime.merges.to_string()),
+        ("Highest tile", lifetime.highest_tile.to_string()),
+        ("Time", format_duration(lifetime.seconds_played)),
+        ("Hints", lifetime.hints_used.to_string()),
+    ];
+
+    commands
+        .spawn_bundle(overlay())
+        .insert(ThemeColor::Overlay)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(label(&font_spec, "Statistics", 40.0))
+                .insert(ThemeColor::Text);
+
+            for (title, stats) in [
+                ("This game", &this_game[..]),
+                ("All games", &all_games[..]),
+            ] {
+                parent
+                    .spawn_bundle(label(&font_spec, title, 28.0))
+                    .insert(ThemeColor::Text);
+                parent.spawn_bundle(row()).with_children(|parent| {
+                    for (name, value) in stats {
+                        parent
+                            .spawn_bundle(label(
+                                &font_spec,
+                                &format!("{} {}", name, value),
+                                20.0,
+                            ))
+                            .insert(ThemeColor::Text);
+                    }
+                });
+            }
+
+            parent
+                .spawn_bundle(label(&font_spec, "Final max tile", 28.0))
+                .insert(ThemeColor::Text);
+            parent.spawn_bundle(row()).with_children(|parent| {
+                if lifetime.final_max_tiles.is_empty() {
+                    parent
+                        .spawn_bundle(label(&font_spec, "-", 20.0))
+                        .insert(ThemeColor::Text);
+                }
+                for (tile, count) in lifetime.final_max_tiles.iter().rev() {
+                    parent
+                        .spawn_bundle(label(
+                            &font_spec,
+                            &format!("{}: {}", tile, count),
+                            20.0,
+                        ))
+                        .insert(ThemeColor::Text);
+                }
+            });
+
+            spawn_button(parent, &font_spec, "Back", 100.0)
+                .insert(StatsBackButton);
+        })
+        .insert(StatsScreen);
+}
+
+fn stats_back_button(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<StatsBackButton>),
+    >,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        let _ = run_state.pop();
+    }
+}
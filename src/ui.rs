@@ -1,6 +1,15 @@
-use crate::colors::{BUTTON_MATERIALS, MATERIALS};
-use crate::{FontSpec, Game, RunState};
-use bevy::prelude::*;
+use crate::{
+    ai::AutoPlay,
+    colors::{Theme, ThemeColor},
+    hint::HintRequest,
+    history::{HistoryAction, UndoHistory},
+    menus::{despawn_with, label, overlay, row},
+    replay::{PendingReplay, ReplayRecorder},
+    stats::format_duration,
+    FontSpec, Game, GameSettings, Points, RunState, MAX_BOARD_SIZE,
+    MIN_BOARD_SIZE,
+};
+use bevy::{ecs::system::EntityCommands, prelude::*, ui::FocusPolicy};
 
 #[derive(Component)]
 pub struct ScoreDisplay;
@@ -8,6 +17,53 @@ pub struct ScoreDisplay;
 #[derive(Component)]
 pub struct BestScoreDisplay;
 
+#[derive(Component)]
+pub struct GameButton;
+
+#[derive(Component)]
+pub struct HistoryButton(HistoryAction);
+
+#[derive(Component)]
+pub struct OpenSettingsButton;
+
+#[derive(Component)]
+pub struct AutoPlayButton;
+
+#[derive(Component)]
+pub struct HintButton;
+
+#[derive(Component)]
+pub struct OpenReplayButton;
+
+#[derive(Component)]
+pub struct OpenStatsButton;
+
+#[derive(Component)]
+pub struct BoardSizeButton {
+    step: i8,
+}
+
+#[derive(Component)]
+pub struct BoardSizeDisplay;
+
+#[derive(Component)]
+pub struct WinOverlay;
+
+#[derive(Component)]
+pub struct KeepGoingButton;
+
+#[derive(Component)]
+pub struct NewGameButton;
+
+#[derive(Component)]
+pub struct GameOverOverlay;
+
+#[derive(Component)]
+pub enum GameOverButton {
+    TryAgain,
+    UndoLastMove,
+}
+
 pub struct GameUiPlugin;
 
 impl Plugin for GameUiPlugin {
@@ -15,7 +71,50 @@ impl Plugin for GameUiPlugin {
         app.add_startup_system(setup_ui)
             .add_system(scoreboard)
             .add_system(button_interaction_system)
-            .add_system(button_text_system);
+            .add_system(game_button_system)
+            .add_system(history_button_system)
+            .add_system(settings_button_system)
+            .add_system(auto_play_button_system)
+            .add_system(hint_button_system)
+            .add_system(replay_button_system)
+            .add_system(stats_button_system)
+            .add_system(auto_play_text_system)
+            .add_system(board_size_button_system)
+            .add_system(board_size_text_system)
+            .add_system(button_text_system)
+            .add_system_set(
+                SystemSet::on_enter(RunState::Won)
+                    .with_system(spawn_win_overlay),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::Won)
+                    .with_system(win_overlay_buttons),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::Won)
+                    .with_system(despawn_win_overlay),
+            )
+            // taken down while stats, settings or a replay sit on top
+            .add_system_set(
+                SystemSet::on_enter(RunState::GameOver)
+                    .with_system(spawn_game_over_overlay),
+            )
+            .add_system_set(
+                SystemSet::on_resume(RunState::GameOver)
+                    .with_system(spawn_game_over_overlay),
+            )
+            .add_system_set(
+                SystemSet::on_update(RunState::GameOver)
+                    .with_system(game_over_overlay_buttons),
+            )
+            .add_system_set(
+                SystemSet::on_pause(RunState::GameOver)
+                    .with_system(despawn_with::<GameOverOverlay>),
+            )
+            .add_system_set(
+                SystemSet::on_exit(RunState::GameOver)
+                    .with_system(despawn_with::<GameOverOverlay>),
+            );
     }
 }
 
@@ -31,7 +130,7 @@ fn setup_ui(
                 padding: UiRect::all(Val::Px(50.0)),
                 ..Default::default()
             },
-            color: UiColor(MATERIALS.none),
+            color: UiColor(Color::NONE),
             ..Default::default()
         })
         //title
@@ -47,7 +146,8 @@ fn setup_ui(
                     TextAlignment::default(),
                 ),
                 ..Default::default()
-            });
+            })
+            .insert(ThemeColor::Text);
 
             parent
                 .spawn_bundle(NodeBundle {
@@ -56,7 +156,7 @@ fn setup_ui(
                         size: Size::new(Val::Percent(100.0), Val::Auto),
                         ..Default::default()
                     },
-                    color: UiColor(MATERIALS.none),
+                    color: UiColor(Color::NONE),
                     ..Default::default()
                 })
                 .with_children(|parent| {
@@ -75,9 +175,9 @@ fn setup_ui(
                                 padding: UiRect::all(Val::Px(10.0)),
                                 ..Default::default()
                             },
-                            color: UiColor(MATERIALS.tile_placeholder),
                             ..Default::default()
                         })
+                        .insert(ThemeColor::Panel)
                         .with_children(|parent| {
                             parent.spawn_bundle(TextBundle {
                                 text: Text::from_section(
@@ -93,7 +193,8 @@ fn setup_ui(
                                     },
                                 ),
                                 ..Default::default()
-                            });
+                            })
+                            .insert(ThemeColor::Text);
                             parent
                                 .spawn_bundle(TextBundle {
                                     text: Text::from_section(
@@ -110,7 +211,8 @@ fn setup_ui(
                                     ),
                                     ..Default::default()
                                 })
-                                .insert(ScoreDisplay);
+                                .insert(ScoreDisplay)
+                                .insert(ThemeColor::Text);
                         });
                     // end scorebox
                     // best scorebox
@@ -122,9 +224,9 @@ fn setup_ui(
                                 padding: UiRect::all(Val::Px(10.0)),
                                 ..Default::default()
                             },
-                            color: UiColor(MATERIALS.tile_placeholder),
                             ..Default::default()
                         })
+                        .insert(ThemeColor::Panel)
                         .with_children(|parent| {
                             parent.spawn_bundle(TextBundle {
                                 text: Text::from_section(
@@ -140,7 +242,8 @@ fn setup_ui(
                                     },
                                 ),
                                 ..Default::default()
-                            });
+                            })
+                            .insert(ThemeColor::Text);
                             parent
                                 .spawn_bundle(TextBundle {
                                     text: Text::from_section(
@@ -157,35 +260,259 @@ fn setup_ui(
                                     ),
                                     ..Default::default()
                                 })
-                                .insert(BestScoreDisplay);
+                                .insert(BestScoreDisplay)
+                                .insert(ThemeColor::Text);
                         });
                     // end best scorebox
                 });
+            spawn_button(parent, &font_spec, "Button", 100.0)
+                .insert(GameButton);
+            spawn_button(parent, &font_spec, "Undo", 60.0)
+                .insert(HistoryButton(HistoryAction::Undo));
+            spawn_button(parent, &font_spec, "Redo", 60.0)
+                .insert(HistoryButton(HistoryAction::Redo));
+            spawn_button(parent, &font_spec, "Settings", 100.0)
+                .insert(OpenSettingsButton);
+            spawn_button(parent, &font_spec, "Auto", 100.0)
+                .insert(AutoPlayButton);
+            spawn_button(parent, &font_spec, "Hint", 60.0)
+                .insert(HintButton);
+            spawn_button(parent, &font_spec, "Replay", 80.0)
+                .insert(OpenReplayButton);
+            spawn_button(parent, &font_spec, "Stats", 80.0)
+                .insert(OpenStatsButton);
+
+            // board size picker
             parent
-                .spawn_bundle(ButtonBundle {
+                .spawn_bundle(NodeBundle {
                     style: Style {
-                        size: Size::new(Val::Px(100.0), Val::Px(30.0)),
-                        justify_content: JustifyContent::Center,
                         align_items: AlignItems::Center,
+                        margin: UiRect {
+                            left: Val::Px(20.0),
+                            ..Default::default()
+                        },
                         ..Default::default()
                     },
+                    color: UiColor(Color::NONE),
                     ..Default::default()
                 })
                 .with_children(|parent| {
-                    parent.spawn_bundle(TextBundle {
-                        text: Text::from_section(
-                            "Button",
-                            TextStyle {
-                                font: font_spec.family.clone(),
-                                font_size: 20.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
-                            }),
-                        ..Default::default()
-                    });
+                    spawn_button(parent, &font_spec, "-", 30.0)
+                        .insert(BoardSizeButton { step: -1 });
+                    parent
+                        .spawn_bundle(TextBundle {
+                            text: Text::from_section(
+                                "<size>",
+                                TextStyle {
+                                    font: font_spec.family.clone(),
+                                    font_size: 20.0,
+                                    color: Color::WHITE,
+                                },
+                            ),
+                            style: Style {
+                                margin: UiRect::all(Val::Px(10.0)),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .insert(BoardSizeDisplay)
+                        .insert(ThemeColor::Text);
+                    spawn_button(parent, &font_spec, "+", 30.0)
+                        .insert(BoardSizeButton { step: 1 });
                 });
+            // end board size picker
         });
 }
 
+fn spawn_win_overlay(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(ThemeColor::Scrim)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    "You win!",
+                    TextStyle {
+                        font: font_spec.family.clone(),
+                        font_size: 60.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                style: Style {
+                    margin: UiRect::all(Val::Px(20.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(ThemeColor::Text);
+            parent
+                .spawn_bundle(NodeBundle {
+                    color: UiColor(Color::NONE),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    spawn_button(parent, &font_spec, "Keep going", 120.0)
+                        .insert(KeepGoingButton);
+                    spawn_button(parent, &font_spec, "New game", 120.0)
+                        .insert(NewGameButton);
+                });
+        })
+        .insert(WinOverlay);
+}
+
+fn win_overlay_buttons(
+    keep_going: Query<
+        &Interaction,
+        (Changed<Interaction>, With<KeepGoingButton>),
+    >,
+    new_game: Query<
+        &Interaction,
+        (Changed<Interaction>, With<NewGameButton>),
+    >,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if keep_going.iter().any(|i| *i == Interaction::Clicked) {
+        // `Playing` was only paused underneath, so the board and
+        // score carry on untouched
+        let _ = run_state.pop();
+    } else if new_game.iter().any(|i| *i == Interaction::Clicked) {
+        // clears the `Playing` underneath too, rather than stacking a
+        // new one on top of it
+        let _ = run_state.replace(RunState::Playing);
+    }
+}
+
+fn despawn_win_overlay(
+    mut commands: Commands,
+    overlays: Query<Entity, With<WinOverlay>>,
+) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn spawn_game_over_overlay(
+    mut commands: Commands,
+    font_spec: Res<FontSpec>,
+    game: Res<Game>,
+    settings: Res<GameSettings>,
+    history: Res<UndoHistory>,
+    tiles: Query<&Points>,
+) {
+    let max_tile = tiles.iter().map(|points| points.value).max().unwrap_or(0);
+    let summary = [
+        ("Score", game.score.to_string()),
+        ("Best", game.score_best.to_string()),
+        ("Max tile", max_tile.to_string()),
+        ("Moves", game.moves.to_string()),
+        ("Time", format_duration(f64::from(game.seconds))),
+    ];
+
+    commands
+        .spawn_bundle(overlay())
+        // light enough to read the final board through
+        .insert(ThemeColor::Scrim)
+        // clicks outside the summary reach the panel underneath: the
+        // size picker, replay and stats buttons
+        .insert(FocusPolicy::Pass)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(label(&font_spec, "Game over", 60.0))
+                .insert(ThemeColor::Text);
+            parent.spawn_bundle(row()).with_children(|parent| {
+                for (name, value) in summary {
+                    parent
+                        .spawn_bundle(label(
+                            &font_spec,
+                            &format!("{} {}", name, value),
+                            24.0,
+                        ))
+                        .insert(ThemeColor::Text);
+                }
+            });
+            parent.spawn_bundle(row()).with_children(|parent| {
+                spawn_button(parent, &font_spec, "Try again", 120.0)
+                    .insert(GameOverButton::TryAgain);
+                if history.can_undo(settings.undo_budget) {
+                    spawn_button(parent, &font_spec, "Undo last move", 160.0)
+                        .insert(GameOverButton::UndoLastMove);
+                }
+            });
+        })
+        .insert(GameOverOverlay);
+}
+
+fn game_over_overlay_buttons(
+    interaction_query: Query<
+        (&Interaction, &GameOverButton),
+        Changed<Interaction>,
+    >,
+    mut run_state: ResMut<State<RunState>>,
+    mut history_writer: EventWriter<HistoryAction>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            GameOverButton::TryAgain => {
+                let _ = run_state.replace(RunState::Playing);
+            }
+            // `apply_history` hands the board back to `Playing` once
+            // the move is taken back
+            GameOverButton::UndoLastMove => {
+                history_writer.send(HistoryAction::Undo);
+            }
+        }
+        return;
+    }
+}
+
+pub fn spawn_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    font_spec: &FontSpec,
+    label: &str,
+    width: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut button = parent.spawn_bundle(ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(width), Val::Px(30.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    button.with_children(|parent| {
+        parent
+            .spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    label,
+                    TextStyle {
+                        font: font_spec.family.clone(),
+                        font_size: 20.0,
+                        ..Default::default()
+                    }),
+                ..Default::default()
+            })
+            .insert(ThemeColor::ButtonText);
+    });
+    button
+}
+
 fn scoreboard(
     game: Res<Game>,
     mut query_scores: ParamSet<(
@@ -207,40 +534,207 @@ fn button_interaction_system(
         (&Interaction, &mut UiColor),
         (Changed<Interaction>, With<Button>),
     >,
-    mut run_state: ResMut<State<RunState>>,
+    theme: Res<Theme>,
 ) {
     for (interaction, mut color) in
         interaction_query.iter_mut()
     {
-        match interaction {
-            Interaction::Clicked => {
-                *color = BUTTON_MATERIALS.pressed.into();
-
-                match run_state.current() {
-                    RunState::Playing => {
-                        run_state
-                            .set(RunState::GameOver)
-                            .unwrap();
-                    }
-                    RunState::GameOver => {
-                        run_state
-                            .set(RunState::Playing)
-                            .unwrap();
-                    }
-                }
-            }
-            Interaction::Hovered => {
-                *color = BUTTON_MATERIALS.hovered.into();
+        *color = theme.button_color(*interaction).into();
+    }
+}
+
+fn game_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<GameButton>),
+    >,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    for interaction in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match run_state.current() {
+            RunState::Playing => {
+                let _ = run_state.push(RunState::GameOver);
             }
-            Interaction::None => {
-                *color = BUTTON_MATERIALS.normal.into();
+            RunState::Won | RunState::GameOver => {
+                let _ = run_state.replace(RunState::Playing);
             }
+            RunState::MainMenu
+            | RunState::Paused
+            | RunState::Settings
+            | RunState::Replay
+            | RunState::Stats => {}
+        }
+    }
+}
+
+/// Settings sit on top of whatever was going on and hand it back
+/// untouched.
+fn settings_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<OpenSettingsButton>),
+    >,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+        && *run_state.current() != RunState::Settings
+    {
+        let _ = run_state.push(RunState::Settings);
+    }
+}
+
+fn auto_play_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<AutoPlayButton>),
+    >,
+    mut auto_play: ResMut<AutoPlay>,
+) {
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        auto_play.0 = !auto_play.0;
+    }
+}
+
+fn auto_play_text_system(
+    auto_play: Res<AutoPlay>,
+    button_query: Query<&Children, With<AutoPlayButton>>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !auto_play.is_changed() {
+        return;
+    }
+    for children in button_query.iter() {
+        let mut text = text_query
+            .get_mut(children[0])
+            .expect("expect button to have a text child");
+        text.sections[0].value = if auto_play.0 {
+            "Stop auto".to_string()
+        } else {
+            "Auto".to_string()
+        };
+    }
+}
+
+fn hint_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<HintButton>),
+    >,
+    run_state: Res<State<RunState>>,
+    mut hint_writer: EventWriter<HintRequest>,
+) {
+    if *run_state.current() == RunState::Playing
+        && interaction_query
+            .iter()
+            .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        hint_writer.send(HintRequest);
+    }
+}
+
+/// Plays back the game so far; `ReplayPlugin` opens the viewer.
+fn replay_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<OpenReplayButton>),
+    >,
+    recorder: Res<ReplayRecorder>,
+    run_state: Res<State<RunState>>,
+    mut pending: ResMut<PendingReplay>,
+) {
+    if matches!(
+        run_state.current(),
+        RunState::Playing | RunState::GameOver
+    ) && interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        pending.0 = recorder.replay().cloned();
+    }
+}
+
+fn stats_button_system(
+    interaction_query: Query<
+        &Interaction,
+        (Changed<Interaction>, With<OpenStatsButton>),
+    >,
+    mut run_state: ResMut<State<RunState>>,
+) {
+    if matches!(
+        run_state.current(),
+        RunState::Playing | RunState::Won | RunState::GameOver
+    ) && interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        let _ = run_state.push(RunState::Stats);
+    }
+}
+
+fn history_button_system(
+    interaction_query: Query<
+        (&Interaction, &HistoryButton),
+        Changed<Interaction>,
+    >,
+    run_state: Res<State<RunState>>,
+    mut history_writer: EventWriter<HistoryAction>,
+) {
+    if *run_state.current() != RunState::Playing {
+        return;
+    }
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            history_writer.send(button.0);
         }
     }
 }
 
+/// The board size can only change between games.
+fn board_size_button_system(
+    interaction_query: Query<
+        (&Interaction, &BoardSizeButton),
+        Changed<Interaction>,
+    >,
+    run_state: Res<State<RunState>>,
+    mut settings: ResMut<GameSettings>,
+) {
+    if *run_state.current() != RunState::GameOver {
+        return;
+    }
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let size = (settings.board_size as i8 + button.step)
+            .clamp(MIN_BOARD_SIZE as i8, MAX_BOARD_SIZE as i8)
+            as u8;
+        if size != settings.board_size {
+            settings.board_size = size;
+        }
+    }
+}
+
+fn board_size_text_system(
+    settings: Res<GameSettings>,
+    mut query: Query<&mut Text, With<BoardSizeDisplay>>,
+) {
+    let mut text = query.single_mut();
+    text.sections[0].value = format!(
+        "{size}x{size}",
+        size = settings.board_size
+    );
+}
+
 fn button_text_system(
-    button_query: Query<&Children, With<Button>>,
+    button_query: Query<&Children, With<GameButton>>,
     mut text_query: Query<&mut Text>,
     run_state: Res<State<RunState>>,
 ) {
@@ -255,8 +749,13 @@ fn button_text_system(
         RunState::Playing => {
             text.sections[0].value = "End Game".to_string();
         }
-        RunState::GameOver => {
+        RunState::Won | RunState::GameOver => {
             text.sections[0].value = "New Game".to_string();
         }
+        RunState::MainMenu
+        | RunState::Paused
+        | RunState::Settings
+        | RunState::Replay
+        | RunState::Stats => {}
     }
 }
\ No newline at end of file